@@ -1,19 +1,52 @@
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+#[path = "src/mime.rs"]
+mod mime;
+
+fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(directory).expect("Failed to read html directory") {
+        let path = entry.expect("Failed to read html directory").path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+// FNV-1a; the ETag only has to change when the contents do.
+fn get_hash(contents: &[u8]) -> u64 {
+    contents.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3))
+}
 
 fn main() {
-    let out_dir = env::var("OUT_DIR").unwrap();
-    let target_dir = PathBuf::from(out_dir)
-        .ancestors()
-        .nth(4)
-        .unwrap()
-        .to_path_buf();
-    let profile = env::var("PROFILE").expect("Failed to get profile");
-
-    let html_path = PathBuf::from("html");
-    let dest_path = target_dir.join(profile);
-
-    fs::copy(html_path.join("hello.html"), dest_path.join("hello.html")).expect("Failed to copy file");
-    fs::copy(html_path.join("404.html"), dest_path.join("404.html")).expect("Failed to copy file");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let html_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("html");
+
+    println!("cargo:rerun-if-changed=html");
+    println!("cargo:rerun-if-changed=src/mime.rs");
+
+    let mut files = Vec::new();
+    collect_files(&html_path, &mut files);
+    files.sort();
+
+    // Every file in html/ is compiled into the binary, keyed by its path relative to html/.
+    let mut assets = String::from("static EMBEDDED: &[Asset] = &[\n");
+    for file in files {
+        let contents = fs::read(&file).expect("Failed to read asset");
+        let name = file.strip_prefix(&html_path).unwrap().to_string_lossy().replace('\\', "/");
+
+        assets.push_str(&format!(
+            "    Asset {{ path: {:?}, contents: include_bytes!({:?}), etag: {:?}, content_type: {:?} }},\n",
+            name,
+            file.to_string_lossy(),
+            format!("\"{:016x}\"", get_hash(&contents)),
+            mime::get_content_type(&file)
+        ));
+    }
+    assets.push_str("];\n");
+
+    fs::write(out_dir.join("assets.rs"), assets).expect("Failed to write embedded assets");
 }
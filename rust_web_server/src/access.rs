@@ -0,0 +1,114 @@
+use std::net::IpAddr;
+use crate::{http::{HttpResponse, Request}, middleware::{Middleware, Next}};
+
+#[derive(Debug)]
+pub enum AccessError {
+    InvalidCidr(String)
+}
+
+/// An address range such as `10.0.0.0/8` or `2001:db8::/32`; a bare address matches only itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_length: u8
+}
+
+fn get_mask(prefix_length: u8, bits: u8) -> u128 {
+    match prefix_length {
+        0 => 0,
+        length => (u128::MAX << (128 - length)) >> (128 - bits)
+    }
+}
+
+impl Cidr {
+    pub fn parse(input: &str) -> Result<Self, AccessError> {
+        let invalid = || AccessError::InvalidCidr(input.to_string());
+        let (address, prefix_length) = match input.trim().split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length.parse::<u8>().map_err(|_| invalid())?)),
+            None => (input.trim(), None)
+        };
+
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_length = prefix_length.unwrap_or(bits);
+        if prefix_length > bits {
+            return Err(invalid());
+        }
+
+        let mask = get_mask(prefix_length, bits);
+        let network = match address {
+            IpAddr::V4(address) => IpAddr::from((u32::from(address) & mask as u32).to_be_bytes()),
+            IpAddr::V6(address) => IpAddr::from((u128::from(address) & mask).to_be_bytes())
+        };
+
+        Ok(Cidr { network, prefix_length })
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = get_mask(self.prefix_length, 32) as u32;
+                u32::from(address) & mask == u32::from(network)
+            },
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                u128::from(address) & get_mask(self.prefix_length, 128) == u128::from(network)
+            },
+            _ => false
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Allow,
+    Deny
+}
+
+/// Allow and deny rules checked in the order they were added; the first matching range decides,
+/// and requests matching none get the default (allow unless changed).
+pub struct AccessControl {
+    rules: Vec<(Access, Cidr)>,
+    default_access: Access
+}
+
+impl Default for AccessControl {
+    fn default() -> Self {
+        AccessControl::new()
+    }
+}
+
+impl AccessControl {
+    pub fn new() -> Self {
+        AccessControl { rules: Vec::new(), default_access: Access::Allow }
+    }
+
+    pub fn allow(mut self, cidr: &str) -> Result<Self, AccessError> {
+        self.rules.push((Access::Allow, Cidr::parse(cidr)?));
+        Ok(self)
+    }
+
+    pub fn deny(mut self, cidr: &str) -> Result<Self, AccessError> {
+        self.rules.push((Access::Deny, Cidr::parse(cidr)?));
+        Ok(self)
+    }
+
+    pub fn default_access(mut self, default_access: Access) -> Self {
+        self.default_access = default_access;
+        self
+    }
+
+    pub fn check(&self, address: Option<IpAddr>) -> Access {
+        address
+            .and_then(|address| self.rules.iter().find(|(_, cidr)| cidr.contains(address)))
+            .map_or(self.default_access, |(access, _)| *access)
+    }
+}
+
+impl Middleware for AccessControl {
+    fn handle(&self, request: &mut Request, next: Next) -> HttpResponse {
+        match self.check(request.remote_addr.map(|addr| addr.ip())) {
+            Access::Allow => next.run(request),
+            Access::Deny => HttpResponse::error(403)
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::{borrow::Cow, fs, io, path::PathBuf, time::{SystemTime, UNIX_EPOCH}};
+use crate::{http::{HttpResponse, Request}, mime::get_content_type, static_files::resolve_path};
+
+/// A file from html/ compiled into the binary by build.rs.
+pub struct Asset {
+    pub path: &'static str,
+    pub contents: &'static [u8],
+    pub etag: &'static str,
+    pub content_type: &'static str
+}
+
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+
+pub fn get_embedded(path: &str) -> Option<&'static Asset> {
+    EMBEDDED.iter().find(|asset| asset.path == path)
+}
+
+pub fn embedded() -> &'static [Asset] {
+    EMBEDDED
+}
+
+pub struct AssetFile {
+    pub contents: Cow<'static, [u8]>,
+    pub etag: String,
+    pub content_type: &'static str
+}
+
+fn get_not_found_error(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no asset named {name}"))
+}
+
+/// Where html/ comes from: compiled into the binary, or read from a directory on every
+/// request so edits show up without rebuilding (for development).
+#[derive(Clone, Debug)]
+pub enum Assets {
+    Embedded,
+    Disk(PathBuf)
+}
+
+impl Assets {
+    /// `name` is relative to html/ with `/` separators; a directory name means its index.html.
+    pub fn get(&self, name: &str) -> io::Result<AssetFile> {
+        match self {
+            Assets::Embedded => {
+                let mut segments = Vec::new();
+                for segment in name.split('/') {
+                    match segment {
+                        "" | "." => continue,
+                        ".." => return Err(get_not_found_error(name)),
+                        segment => segments.push(segment)
+                    }
+                }
+
+                let path = segments.join("/");
+                let index_path = match path.as_str() {
+                    "" => "index.html".to_string(),
+                    path => format!("{path}/index.html")
+                };
+                let asset = get_embedded(&path).or_else(|| get_embedded(&index_path)).ok_or_else(|| get_not_found_error(name))?;
+
+                Ok(AssetFile { contents: Cow::Borrowed(asset.contents), etag: asset.etag.to_string(), content_type: asset.content_type })
+            },
+            Assets::Disk(directory) => {
+                let path = resolve_path(directory, name).ok_or_else(|| get_not_found_error(name))?;
+                let metadata = fs::metadata(&path)?;
+                let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+                let contents = fs::read(&path)?;
+
+                Ok(AssetFile {
+                    contents: Cow::Owned(contents),
+                    etag: format!("\"{modified:x}-{:x}\"", metadata.len()),
+                    content_type: get_content_type(&path)
+                })
+            }
+        }
+    }
+
+    /// When the file was last changed; always `None` for embedded assets, which never change.
+    pub fn modified(&self, name: &str) -> Option<SystemTime> {
+        match self {
+            Assets::Embedded => None,
+            Assets::Disk(directory) => fs::metadata(resolve_path(directory, name)?).and_then(|metadata| metadata.modified()).ok()
+        }
+    }
+
+    /// Serves GET and HEAD requests for the asset at the request path, answering 304 when `If-None-Match` matches.
+    pub fn serve(&self, request: &Request) -> Option<HttpResponse> {
+        if request.method != "GET" && request.method != "HEAD" {
+            return None;
+        }
+
+        let asset = self.get(request.path()).ok()?;
+        let is_cached = request.header("If-None-Match").is_some_and(|tags| {
+            tags.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == asset.etag)
+        });
+
+        if is_cached {
+            let mut response = HttpResponse::no_content().with_header("ETag", &asset.etag);
+            response.status = 304;
+            return Some(response);
+        }
+
+        Some(HttpResponse::new(200, asset.content_type, asset.contents.into_owned()).with_header("ETag", &asset.etag))
+    }
+}
@@ -0,0 +1,113 @@
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}, sync::Arc};
+use argon2::{password_hash::PasswordHash, Argon2, PasswordVerifier};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crate::{http::{HttpResponse, Request}, middleware::{Middleware, Next}};
+
+#[derive(Debug)]
+pub enum AuthError {
+    Io(PathBuf, io::Error),
+    Malformed(usize),
+    UnsupportedHash(usize)
+}
+
+fn is_bcrypt_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+fn is_argon2_hash(hash: &str) -> bool {
+    ["$argon2id$", "$argon2i$", "$argon2d$"].iter().any(|prefix| hash.starts_with(prefix))
+}
+
+fn verify_hash(hash: &str, password: &str) -> bool {
+    match is_bcrypt_hash(hash) {
+        true => bcrypt::verify(password, hash).unwrap_or(false),
+        false => PasswordHash::new(hash)
+            .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+    }
+}
+
+/// `user:hash` lines as written by `htpasswd -B`, or with argon2 PHC strings. `#` starts a comment.
+pub struct Htpasswd {
+    users: HashMap<String, String>,
+    // Checked for unknown users, so that they take as long to reject as wrong passwords.
+    dummy_hash: Option<String>
+}
+
+impl Htpasswd {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, AuthError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|error| AuthError::Io(path.to_path_buf(), error))?;
+        Htpasswd::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, AuthError> {
+        let mut users = HashMap::new();
+        let mut dummy_hash = None;
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (user, hash) = line.split_once(':').ok_or(AuthError::Malformed(index + 1))?;
+            if !is_bcrypt_hash(hash) && !is_argon2_hash(hash) {
+                return Err(AuthError::UnsupportedHash(index + 1));
+            }
+            dummy_hash.get_or_insert_with(|| hash.to_string());
+            users.insert(user.to_string(), hash.to_string());
+        }
+
+        Ok(Htpasswd { users, dummy_hash })
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        match self.users.get(user) {
+            Some(hash) => verify_hash(hash, password),
+            None => {
+                if let Some(hash) = &self.dummy_hash {
+                    verify_hash(hash, password);
+                }
+                false
+            }
+        }
+    }
+}
+
+/// The user name and password from an `Authorization: Basic` header.
+pub fn basic_credentials(request: &Request) -> Option<(String, String)> {
+    let (scheme, credentials) = request.header("Authorization")?.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+
+    let credentials = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+    let (user, password) = credentials.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// Answers 401 with a `WWW-Authenticate` challenge unless the request carries valid credentials.
+pub struct BasicAuth {
+    realm: String,
+    htpasswd: Arc<Htpasswd>
+}
+
+impl BasicAuth {
+    pub fn new(realm: &str, htpasswd: Arc<Htpasswd>) -> Self {
+        BasicAuth { realm: realm.to_string(), htpasswd }
+    }
+
+    pub fn get_unauthorized_response(&self) -> HttpResponse {
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        HttpResponse::error(401)
+            .with_header("WWW-Authenticate", &format!("Basic realm=\"{realm}\", charset=\"UTF-8\""))
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, request: &mut Request, next: Next) -> HttpResponse {
+        match basic_credentials(request) {
+            Some((user, password)) if self.htpasswd.verify(&user, &password) => next.run(request),
+            _ => self.get_unauthorized_response()
+        }
+    }
+}
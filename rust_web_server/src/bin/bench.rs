@@ -0,0 +1,285 @@
+use std::{collections::BTreeMap, env, io::{self, BufReader, Read, Write}, net::{SocketAddr, TcpStream, ToSocketAddrs}, process, sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread, time::{Duration, Instant}};
+use rust_web_server::{chunked::ChunkedReader, http::{read_response_head, strip_port}};
+
+const USAGE: &str = "\
+Usage: bench <http://host:port/path> [options]
+
+  --connections <n>          concurrent connections (default 8)
+  --duration <seconds>       how long to run (default 10, unless --requests is given)
+  --requests <n>             stop after this many requests in total
+  --rate <n>                 requests per second across all connections (default unlimited)
+  --no-keep-alive            open a new connection for every request
+  --request <method> <path> <weight>
+                             adds a request to the mix, sent in proportion to its weight
+                             (default: GET of the URL's path)
+  --header <name: value>     adds a header to every request
+  --body <text>              sends a body with every request";
+
+struct RequestSpec {
+    method: String,
+    path: String,
+    weight: usize
+}
+
+struct Options {
+    addr: SocketAddr,
+    host: String,
+    connections: usize,
+    duration: Option<Duration>,
+    requests: Option<usize>,
+    rate: Option<f64>,
+    keep_alive: bool,
+    mix: Vec<RequestSpec>,
+    headers: Vec<String>,
+    body: Vec<u8>
+}
+
+#[derive(Default)]
+struct Results {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, usize>,
+    errors: usize,
+    bytes: u64
+}
+
+impl Results {
+    fn merge(&mut self, other: Results) {
+        self.latencies.extend(other.latencies);
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_insert(0) += count;
+        }
+        self.errors += other.errors;
+        self.bytes += other.bytes;
+    }
+}
+
+// Only plain HTTP is supported: http://host[:port][/path]
+fn parse_url(url: &str) -> Result<(SocketAddr, String, String), String> {
+    let rest = url.strip_prefix("http://").ok_or("the URL must start with http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/")
+    };
+
+    let with_port = match strip_port(authority) == authority {
+        true => format!("{authority}:80"),
+        false => authority.to_string()
+    };
+    let addr = with_port.to_socket_addrs().ok().and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| format!("could not resolve {authority}"))?;
+
+    Ok((addr, authority.to_string(), path.to_string()))
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: Option<&String>) -> Result<T, String> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| format!("{option} needs a number"))
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let url = args.first().filter(|url| !url.starts_with("--")).ok_or("missing URL")?;
+    let (addr, host, path) = parse_url(url)?;
+    let mut options = Options {
+        addr,
+        host,
+        connections: 8,
+        duration: None,
+        requests: None,
+        rate: None,
+        keep_alive: true,
+        mix: Vec::new(),
+        headers: Vec::new(),
+        body: Vec::new()
+    };
+
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--connections" => options.connections = parse_number(arg, args.next())?,
+            "--duration" => options.duration = Some(Duration::from_secs_f64(parse_number(arg, args.next())?)),
+            "--requests" => options.requests = Some(parse_number(arg, args.next())?),
+            "--rate" => options.rate = Some(parse_number(arg, args.next())?),
+            "--no-keep-alive" => options.keep_alive = false,
+            "--request" => {
+                let (Some(method), Some(path)) = (args.next(), args.next()) else {
+                    return Err("--request needs a method, a path and a weight".into());
+                };
+                let weight = parse_number(arg, args.next())?;
+                options.mix.push(RequestSpec { method: method.to_ascii_uppercase(), path: path.clone(), weight });
+            },
+            "--header" => options.headers.push(args.next().ok_or("--header needs a value")?.clone()),
+            "--body" => options.body = args.next().ok_or("--body needs a value")?.clone().into_bytes(),
+            _ => return Err(format!("unknown option {arg}"))
+        }
+    }
+
+    if options.connections == 0 || options.rate.is_some_and(|rate| rate <= 0.0) {
+        return Err("--connections and --rate must be positive".into());
+    }
+    if options.mix.is_empty() {
+        options.mix.push(RequestSpec { method: "GET".into(), path, weight: 1 });
+    }
+    if options.mix.iter().all(|spec| spec.weight == 0) {
+        return Err("at least one request needs a positive weight".into());
+    }
+    if options.duration.is_none() && options.requests.is_none() {
+        options.duration = Some(Duration::from_secs(10));
+    }
+
+    Ok(options)
+}
+
+fn format_request(options: &Options, spec: &RequestSpec) -> Vec<u8> {
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", spec.method, spec.path, options.host);
+    for header in &options.headers {
+        head.push_str(&format!("{header}\r\n"));
+    }
+    if !options.body.is_empty() {
+        head.push_str(&format!("Content-Length: {}\r\n", options.body.len()));
+    }
+    if !options.keep_alive {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+
+    let mut request = head.into_bytes();
+    request.extend_from_slice(&options.body);
+    request
+}
+
+fn connect(addr: SocketAddr) -> io::Result<(TcpStream, BufReader<TcpStream>)> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    Ok((stream, reader))
+}
+
+// Returns the status and body length, dropping the connection unless it can carry another request.
+fn send(connection: &mut Option<(TcpStream, BufReader<TcpStream>)>, options: &Options, spec: &RequestSpec, request: &[u8]) -> io::Result<(u16, u64)> {
+    let (stream, reader) = match connection {
+        Some(connection) => connection,
+        None => connection.insert(connect(options.addr)?)
+    };
+
+    stream.write_all(request)?;
+    let (status, headers) = read_response_head(reader)?;
+    let close = headers.get("Connection").is_some_and(|value| value.eq_ignore_ascii_case("close"));
+
+    let (bytes, reusable) = if spec.method == "HEAD" || status == 204 || status == 304 {
+        (0, !close)
+    } else if headers.get("Transfer-Encoding").is_some_and(|coding| coding.eq_ignore_ascii_case("chunked")) {
+        (io::copy(&mut ChunkedReader::new(&mut *reader), &mut io::sink())?, !close)
+    } else if let Some(length) = headers.get("Content-Length").and_then(|length| length.parse::<u64>().ok()) {
+        let copied = io::copy(&mut (&mut *reader).take(length), &mut io::sink())?;
+        if copied < length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "response body cut short"));
+        }
+        (copied, !close)
+    } else {
+        // Delimited by the server closing the connection.
+        (io::copy(reader, &mut io::sink())?, false)
+    };
+
+    if !(reusable && options.keep_alive) {
+        *connection = None;
+    }
+    Ok((status, bytes))
+}
+
+fn run_connection(index: usize, options: &Options, remaining: Option<&AtomicUsize>, start: Instant) -> Results {
+    // Each request in the mix appears as often as its weight; connections start at different points.
+    let schedule: Vec<(&RequestSpec, Vec<u8>)> = options.mix.iter()
+        .flat_map(|spec| std::iter::repeat_n(spec, spec.weight))
+        .map(|spec| (spec, format_request(options, spec)))
+        .collect();
+    let interval = options.rate.map(|rate| Duration::from_secs_f64(options.connections as f64 / rate));
+    let deadline = options.duration.map(|duration| start + duration);
+
+    let mut results = Results::default();
+    let mut connection = None;
+    let mut next_send = start + interval.unwrap_or_default() * index as u32 / options.connections as u32;
+
+    for sent in index.. {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break;
+        }
+        if let Some(remaining) = remaining {
+            if remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1)).is_err() {
+                break;
+            }
+        }
+        if let Some(interval) = interval {
+            thread::sleep(next_send.saturating_duration_since(Instant::now()));
+            next_send += interval;
+        }
+
+        let (spec, request) = &schedule[sent % schedule.len()];
+        let sent_at = Instant::now();
+        match send(&mut connection, options, spec, request) {
+            Ok((status, bytes)) => {
+                results.latencies.push(sent_at.elapsed());
+                *results.statuses.entry(status).or_insert(0) += 1;
+                results.bytes += bytes;
+            },
+            Err(_) => {
+                results.errors += 1;
+                connection = None;
+                // Keeps a refused connection from turning into a busy loop.
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    results
+}
+
+fn get_percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn report(mut results: Results, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    let completed = results.latencies.len();
+    println!("Requests:     {completed} in {elapsed:.2?}, {} errors", results.errors);
+    println!("Throughput:   {:.1} requests/s, {:.2} MiB/s of bodies", completed as f64 / seconds, results.bytes as f64 / seconds / (1024.0 * 1024.0));
+
+    if completed > 0 {
+        results.latencies.sort();
+        let mean = results.latencies.iter().sum::<Duration>() / completed as u32;
+        let percentiles: Vec<String> = [50.0, 90.0, 99.0, 99.9].iter()
+            .map(|percentile| format!("p{percentile} {:.2?}", get_percentile(&results.latencies, *percentile)))
+            .collect();
+        println!("Latency:      mean {mean:.2?}, {}, max {:.2?}", percentiles.join(", "), results.latencies[completed - 1]);
+    }
+
+    let statuses: Vec<String> = results.statuses.iter().map(|(status, count)| format!("{status} x {count}")).collect();
+    println!("Status codes: {}", statuses.join(", "));
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(options) => Arc::new(options),
+        Err(error) => {
+            eprintln!("{error}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    let remaining = options.requests.map(|requests| Arc::new(AtomicUsize::new(requests)));
+
+    let start = Instant::now();
+    let threads: Vec<_> = (0..options.connections)
+        .map(|index| {
+            let options = Arc::clone(&options);
+            let remaining = remaining.clone();
+            thread::spawn(move || run_connection(index, &options, remaining.as_deref(), start))
+        })
+        .collect();
+
+    let mut results = Results::default();
+    for thread in threads {
+        results.merge(thread.join().expect("connection thread panicked"));
+    }
+
+    report(results, start.elapsed());
+}
@@ -0,0 +1,281 @@
+use std::{io::{self, BufRead, BufReader, Cursor, Read, Write}, path::PathBuf, process::{Child, Command, Stdio}, sync::mpsc::{self, Receiver, RecvTimeoutError, Sender}, thread, time::{Duration, Instant}};
+use crate::{form::read_limited, http::{read_headers, strip_port, Body, Headers, HttpResponse, Request}, proxy::get_gateway_error_response};
+
+// Chunked bodies are read into memory to tell the script their length, up to this size.
+const MAX_BUFFERED_BODY: u64 = 16 * 1024 * 1024;
+// The request body goes to the script, and its output comes back, in pieces of up to this size.
+const PIPE_CHUNK_SIZE: usize = 64 * 1024;
+
+// Passed in their own variables, or withheld from the script.
+const EXCLUDED_HEADERS: [&str; 5] = ["Content-Length", "Content-Type", "Authorization", "Proxy-Authorization", "Proxy"];
+
+/// Splits the request path into `SCRIPT_NAME` and `PATH_INFO` at the prefix of the route handling it.
+fn split_script_path(request: &Request) -> (String, String) {
+    let path = request.path();
+    match request.route.as_deref().and_then(|route| route.strip_suffix("/*")) {
+        Some(script_name) => (script_name.to_string(), path.strip_prefix(script_name).unwrap_or_default().to_string()),
+        None => (path.to_string(), String::new())
+    }
+}
+
+/// The RFC 3875 meta-variables describing `request` to a script, with `content_length` as `CONTENT_LENGTH`.
+pub fn get_environment(request: &Request, content_length: u64) -> Vec<(String, String)> {
+    let (script_name, path_info) = split_script_path(request);
+    let query_string = request.target().split_once('?').map(|(_, query)| query).unwrap_or_default();
+    let default_port = if request.secure { "443" } else { "80" };
+    let (server_name, server_port) = match request.host() {
+        Some(host) => (strip_port(host), host.strip_prefix(strip_port(host)).and_then(|port| port.strip_prefix(':')).unwrap_or(default_port)),
+        None => ("", default_port)
+    };
+
+    let mut environment: Vec<(String, String)> = vec![
+        ("GATEWAY_INTERFACE".into(), "CGI/1.1".into()),
+        ("SERVER_SOFTWARE".into(), format!("rust_web_server/{}", env!("CARGO_PKG_VERSION"))),
+        ("SERVER_PROTOCOL".into(), request.version.clone()),
+        ("SERVER_NAME".into(), server_name.into()),
+        ("SERVER_PORT".into(), server_port.into()),
+        ("REQUEST_METHOD".into(), request.method.clone()),
+        ("REQUEST_URI".into(), request.target().into()),
+        ("SCRIPT_NAME".into(), script_name),
+        ("PATH_INFO".into(), path_info),
+        ("QUERY_STRING".into(), query_string.into())
+    ];
+
+    if let Some(remote_addr) = request.remote_addr {
+        environment.push(("REMOTE_ADDR".into(), remote_addr.ip().to_string()));
+        environment.push(("REMOTE_PORT".into(), remote_addr.port().to_string()));
+    }
+    if request.secure {
+        environment.push(("HTTPS".into(), "on".into()));
+    }
+    if content_length > 0 {
+        environment.push(("CONTENT_LENGTH".into(), content_length.to_string()));
+    }
+    if let Some(content_type) = request.header("Content-Type") {
+        environment.push(("CONTENT_TYPE".into(), content_type.into()));
+    }
+
+    for (name, value) in request.headers.iter().filter(|(name, _)| !EXCLUDED_HEADERS.iter().any(|excluded| excluded.eq_ignore_ascii_case(name))) {
+        let name = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+        match environment.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, existing)) => *existing = format!("{existing}, {value}"),
+            None => environment.push((name, value.into()))
+        }
+    }
+
+    environment
+}
+
+/// The length of the body to pass to a script. A chunked body is read into memory first, since
+/// scripts are told the length before they read it.
+pub(crate) fn get_body_length(request: &mut Request) -> Result<u64, HttpResponse> {
+    if !request.headers.contains("Transfer-Encoding") {
+        return Ok(request.content_length().unwrap_or(0));
+    }
+
+    let body = read_limited(request, MAX_BUFFERED_BODY).map_err(|error| error.get_response())?;
+    let length = body.len() as u64;
+    request.body = Box::new(Cursor::new(body));
+    Ok(length)
+}
+
+/// Reads the header section a script writes before its body, returning the status it asked for
+/// with `Status`, or 302 for a `Location` alone, and the remaining headers.
+pub(crate) fn read_script_head<R: BufRead>(reader: &mut R) -> io::Result<(u16, Headers)> {
+    let mut headers = read_headers(reader)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed script response headers"))?;
+
+    let status = match headers.get("Status") {
+        Some(status) => status.split(' ').next().and_then(|code| code.parse().ok())
+            .filter(|code| (200..600).contains(code))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid Status header"))?,
+        None if headers.contains("Location") => 302,
+        None => 200
+    };
+    headers.remove("Status");
+
+    if !headers.contains("Content-Type") && !headers.contains("Location") && status == 200 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "script response has no Content-Type"));
+    }
+
+    Ok((status, headers))
+}
+
+// Owns the running script: kills it at the deadline or when its response is abandoned, and reaps it.
+// Once its output is finished it is given until the deadline to exit.
+struct Watchdog(Sender<()>);
+
+impl Watchdog {
+    fn start(mut child: Child, deadline: Instant) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            if let Ok(()) = receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                while let Ok(None) = child.try_wait() {
+                    if Instant::now() >= deadline {
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+            if let Ok(None) = child.try_wait() {
+                let _ = child.kill();
+            }
+            let _ = child.wait();
+        });
+        Watchdog(sender)
+    }
+
+    fn finish(self) {
+        let _ = self.0.send(());
+    }
+}
+
+/// A CGI program run once per request.
+pub struct Cgi {
+    program: PathBuf,
+    environment: Vec<(String, String)>,
+    working_dir: Option<PathBuf>,
+    timeout: Duration
+}
+
+impl Cgi {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Cgi { program: program.into(), environment: Vec::new(), working_dir: None, timeout: Duration::from_secs(30) }
+    }
+
+    /// Sets an extra environment variable for the program, such as `PATH` or `DOCUMENT_ROOT`.
+    pub fn env(mut self, name: &str, value: &str) -> Self {
+        self.environment.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn working_dir(mut self, working_dir: impl Into<PathBuf>) -> Self {
+        self.working_dir = Some(working_dir.into());
+        self
+    }
+
+    /// Longest the program may take to read the request and write its whole response before it is
+    /// killed. A client still waiting for the response headers then gets 504.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Runs `cgi` for `request` and streams its output back as the response.
+pub fn run(cgi: &Cgi, request: &mut Request) -> HttpResponse {
+    let content_length = match get_body_length(request) {
+        Ok(content_length) => content_length,
+        Err(response) => return response
+    };
+
+    let mut command = Command::new(&cgi.program);
+    command.env_clear()
+        .envs(get_environment(request, content_length))
+        .envs(cgi.environment.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    if let Some(working_dir) = &cgi.working_dir {
+        command.current_dir(working_dir);
+    }
+
+    let deadline = Instant::now() + cgi.timeout;
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(error) => {
+            println!("Failed to run CGI program {}: {error}", cgi.program.display());
+            return HttpResponse::error(502);
+        }
+    };
+    let (stdin, stdout) = (child.stdin.take(), child.stdout.take());
+    let watchdog = Watchdog::start(child, deadline);
+    let (Some(stdin), Some(stdout)) = (stdin, stdout) else {
+        return HttpResponse::error(502);
+    };
+
+    // Output is drained as it comes, so a program writing before it has read all its input is not held up.
+    let (head_sender, head_receiver) = mpsc::channel();
+    let (output_sender, output_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        let head = read_script_head(&mut reader);
+        let failed = head.is_err();
+        if head_sender.send(head).is_err() || failed {
+            return;
+        }
+        drain_output(reader, output_sender);
+    });
+
+    if let Err(error) = feed_input(&mut (&mut request.body).take(content_length), stdin) {
+        println!("Failed to send the request body to {}: {error}", cgi.program.display());
+        return HttpResponse::error(502);
+    }
+
+    let (status, headers) = match head_receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(Ok(response_head)) => response_head,
+        Ok(Err(_)) | Err(_) if Instant::now() >= deadline => {
+            println!("CGI program {} timed out.", cgi.program.display());
+            return HttpResponse::error(504);
+        },
+        Ok(Err(error)) => {
+            println!("CGI program {} failed: {error}", cgi.program.display());
+            return get_gateway_error_response(&error);
+        },
+        Err(_) => return HttpResponse::error(502)
+    };
+
+    HttpResponse { status, headers, body: stream_script_output(output_receiver, deadline, watchdog), upgrade: None, default_body: false }
+}
+
+// Writes the request body to the program from another thread, so the deadline still applies while
+// the program is not reading. A program may answer without reading its input, which closes the pipe early.
+fn feed_input(body: &mut dyn Read, mut stdin: impl Write + Send + 'static) -> io::Result<()> {
+    let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(1);
+    thread::spawn(move || {
+        for chunk in receiver {
+            if stdin.write_all(&chunk).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut chunk = vec![0; PIPE_CHUNK_SIZE];
+    loop {
+        let read = body.read(&mut chunk)?;
+        if read == 0 || sender.send(chunk[..read].to_vec()).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn drain_output(mut reader: impl Read, sender: Sender<io::Result<Vec<u8>>>) {
+    let mut chunk = vec![0; PIPE_CHUNK_SIZE];
+    loop {
+        let output = match reader.read(&mut chunk) {
+            Ok(0) => return,
+            Ok(read) => Ok(chunk[..read].to_vec()),
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => Err(error)
+        };
+        let failed = output.is_err();
+        if sender.send(output).is_err() || failed {
+            return;
+        }
+    }
+}
+
+fn stream_script_output(output: Receiver<io::Result<Vec<u8>>>, deadline: Instant, watchdog: Watchdog) -> Body {
+    Body::Stream(Box::new(move |writer: &mut dyn Write| {
+        loop {
+            match output.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(chunk) => writer.write_all(&chunk?)?,
+                // Output cut short by the watchdog is a timeout, not the end of the response.
+                Err(RecvTimeoutError::Disconnected) if Instant::now() < deadline => break,
+                Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "CGI program timed out"))
+            }
+        }
+        watchdog.finish();
+        Ok(())
+    }))
+}
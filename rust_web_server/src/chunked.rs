@@ -0,0 +1,139 @@
+use std::{io::{self, BufRead, Read, Write}, mem, sync::{Arc, Mutex}};
+use crate::http::{into_io_error, read_headers, read_line, Body, Headers, HttpResponse};
+
+// Longer chunk size lines, extensions included, are rejected.
+const MAX_SIZE_LINE: u64 = 4096;
+
+fn get_invalid_chunk_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid chunked body: {message}"))
+}
+
+/// Decodes a chunked body. Trailers become available through `trailers` once it has been read to the end.
+pub struct ChunkedReader<R: BufRead> {
+    reader: R,
+    remaining: u64,
+    done: bool,
+    failed: bool,
+    trailers: Arc<Mutex<Headers>>
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(reader: R) -> Self {
+        ChunkedReader { reader, remaining: 0, done: false, failed: false, trailers: Arc::new(Mutex::new(Headers::new())) }
+    }
+
+    pub fn trailers(&self) -> Arc<Mutex<Headers>> {
+        Arc::clone(&self.trailers)
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut reader = (&mut self.reader).take(MAX_SIZE_LINE);
+        read_line(&mut reader).map_err(into_io_error)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "chunked body cut short"))
+    }
+
+    fn start_chunk(&mut self) -> io::Result<()> {
+        let line = self.read_line()?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        if size.is_empty() || !size.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(get_invalid_chunk_error("bad chunk size"));
+        }
+        self.remaining = u64::from_str_radix(size, 16).map_err(|_| get_invalid_chunk_error("chunk too large"))?;
+
+        if self.remaining == 0 {
+            let trailers = read_headers(&mut self.reader).map_err(into_io_error)?;
+            *self.trailers.lock().unwrap() = trailers;
+            self.done = true;
+        }
+        Ok(())
+    }
+
+    fn read_chunk(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.done {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.start_chunk()?;
+            if self.done {
+                return Ok(0);
+            }
+        }
+
+        let limit = buf.len().min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = self.reader.read(&mut buf[..limit])?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "chunked body cut short"));
+        }
+
+        self.remaining -= read as u64;
+        if self.remaining == 0 && !self.read_line()?.is_empty() {
+            return Err(get_invalid_chunk_error("chunk longer than its size"));
+        }
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Past an error the position in the body is lost, so whatever follows cannot be trusted.
+        if self.failed {
+            return Err(get_invalid_chunk_error("read after an earlier error"));
+        }
+
+        let result = self.read_chunk(buf);
+        self.failed = result.is_err();
+        result
+    }
+}
+
+/// Encodes everything written to it as chunks; `finish` writes the last, empty one.
+pub struct ChunkedWriter<W: Write> {
+    writer: W
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(writer: W) -> Self {
+        ChunkedWriter { writer }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(b"0\r\n\r\n")?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        write!(self.writer, "{:x}\r\n", buf.len())?;
+        self.writer.write_all(buf)?;
+        self.writer.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl HttpResponse {
+    /// Sends a streamed body as chunks, so the connection can be reused after it.
+    pub(crate) fn into_chunked(mut self) -> Self {
+        let Body::Stream(stream) = mem::replace(&mut self.body, Body::Bytes(Vec::new())) else {
+            return self;
+        };
+
+        self.headers.insert("Transfer-Encoding", "chunked");
+        self.body = Body::Stream(Box::new(move |writer| {
+            let mut chunked = ChunkedWriter::new(writer);
+            stream(&mut chunked)?;
+            chunked.finish()?;
+            Ok(())
+        }));
+        self
+    }
+}
@@ -0,0 +1,209 @@
+use std::time::Duration;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use crate::{http::{HttpResponse, Request}, url};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None
+}
+
+// Escapes bytes a cookie name or value may not contain, such as `;`, `,` and line breaks, and `%` itself;
+// `parse_cookies` decodes them again.
+fn encode_cookie_part(input: &str, is_allowed: fn(u8) -> bool) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'%' => encoded.push_str("%25"),
+            byte if is_allowed(byte) => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{byte:02X}"))
+        }
+    }
+    encoded
+}
+
+// RFC 6265 cookie-octet.
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+// RFC 9110 token character.
+fn is_token_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$&'*+-.^_`|~".contains(&byte)
+}
+
+/// A `Set-Cookie` value; attributes left unset are omitted.
+#[derive(Clone, Debug)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None
+        }
+    }
+
+    /// A cookie that tells the client to delete `name` right away.
+    pub fn removal(name: &str) -> Self {
+        Cookie::new(name, "").max_age(Duration::ZERO)
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// `SameSite=None` is only honoured by browsers together with `Secure`.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The `Set-Cookie` value, with characters the name and value may not contain percent-encoded.
+    pub fn to_header(&self) -> String {
+        let mut header = format!("{}={}", encode_cookie_part(&self.name, is_token_char), encode_cookie_part(&self.value, is_cookie_octet));
+        if let Some(path) = &self.path {
+            header.push_str(&format!("; Path={path}"));
+        }
+        if let Some(domain) = &self.domain {
+            header.push_str(&format!("; Domain={domain}"));
+        }
+        if let Some(max_age) = self.max_age {
+            header.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        if self.secure {
+            header.push_str("; Secure");
+        }
+        if self.http_only {
+            header.push_str("; HttpOnly");
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => header.push_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => header.push_str("; SameSite=Lax"),
+            Some(SameSite::None) => header.push_str("; SameSite=None"),
+            None => {}
+        }
+        header
+    }
+}
+
+/// Parses a `Cookie` request header into name/value pairs, in order, decoding percent-escapes.
+pub fn parse_cookies(header: &str) -> Vec<(String, String)> {
+    let decode = |text: &str| url::percent_decode(text).unwrap_or_else(|| text.to_string());
+    header.split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| {
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value);
+            (decode(name.trim()), decode(value))
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .collect()
+}
+
+impl Request<'_> {
+    pub fn cookies(&self) -> Vec<(String, String)> {
+        self.headers.get_all("Cookie").flat_map(parse_cookies).collect()
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies().into_iter().find(|(key, _)| key == name).map(|(_, value)| value)
+    }
+}
+
+impl HttpResponse {
+    /// Adds a `Set-Cookie` header, keeping any already set.
+    pub fn with_cookie(mut self, cookie: &Cookie) -> Self {
+        self.headers.append("Set-Cookie", cookie.to_header());
+        self
+    }
+}
+
+/// Signs cookie values with HMAC-SHA256 so that clients cannot forge or swap them.
+#[derive(Clone)]
+pub struct CookieSigner {
+    key: Vec<u8>
+}
+
+impl CookieSigner {
+    pub fn new(key: &[u8]) -> Self {
+        CookieSigner { key: key.to_vec() }
+    }
+
+    fn get_mac(&self, name: &str, value: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    /// Returns `value` with its signature appended; the name is covered by the signature too.
+    pub fn sign(&self, name: &str, value: &str) -> String {
+        let signature = self.get_mac(name, value).finalize().into_bytes();
+        format!("{value}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    /// Returns the original value if `signed` was produced by `sign` for this name and key.
+    pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (value, signature) = signed.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.get_mac(name, value).verify_slice(&signature).ok()?;
+        Some(value.to_string())
+    }
+
+    pub fn signed_cookie(&self, name: &str, value: &str) -> Cookie {
+        Cookie::new(name, &self.sign(name, value))
+    }
+
+    pub fn get_signed(&self, request: &Request, name: &str) -> Option<String> {
+        self.verify(name, &request.cookie(name)?)
+    }
+}
@@ -0,0 +1,208 @@
+use std::time::Duration;
+use regex::Regex;
+use crate::{http::{HttpResponse, Request}, middleware::{Middleware, Next}};
+
+enum AllowedOrigin {
+    Any,
+    Exact(String),
+    // `https://*.example.com`: any non-empty host label sequence in place of the `*`.
+    Wildcard(String, String),
+    Regex(Regex)
+}
+
+impl AllowedOrigin {
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            AllowedOrigin::Any => true,
+            AllowedOrigin::Exact(allowed) => allowed.eq_ignore_ascii_case(origin),
+            AllowedOrigin::Wildcard(prefix, suffix) => {
+                let origin = origin.to_ascii_lowercase();
+                origin.len() > prefix.len() + suffix.len()
+                    && origin.starts_with(prefix.as_str())
+                    && origin.ends_with(suffix.as_str())
+                    && !origin[prefix.len()..origin.len() - suffix.len()].contains(['/', ':'])
+            },
+            AllowedOrigin::Regex(regex) => regex.is_match(origin)
+        }
+    }
+}
+
+fn add_vary(response: &mut HttpResponse, names: &[&str]) {
+    let mut vary: Vec<String> = response.headers.get("Vary")
+        .map(|vary| vary.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect())
+        .unwrap_or_default();
+
+    for name in names {
+        if !vary.iter().any(|existing| existing == "*" || existing.eq_ignore_ascii_case(name)) {
+            vary.push(name.to_string());
+        }
+    }
+    response.headers.insert("Vary", vary.join(", "));
+}
+
+/// A CORS policy; use as middleware, or with `Router::cors` to also answer preflight requests for a route.
+///
+/// Nothing is allowed until origins are added. Methods default to GET, HEAD and POST.
+pub struct Cors {
+    origins: Vec<AllowedOrigin>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    exposed_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors::new()
+    }
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Cors {
+            origins: Vec::new(),
+            methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+            headers: Vec::new(),
+            exposed_headers: Vec::new(),
+            credentials: false,
+            max_age: None
+        }
+    }
+
+    /// An exact origin such as `https://app.example.com`, a wildcard such as `https://*.example.com`, or `*` for any.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        let origin = origin.trim().trim_end_matches('/').to_ascii_lowercase();
+        let allowed = match origin.split_once('*') {
+            _ if origin == "*" => AllowedOrigin::Any,
+            Some((prefix, suffix)) => AllowedOrigin::Wildcard(prefix.to_string(), suffix.to_string()),
+            None => AllowedOrigin::Exact(origin)
+        };
+        self.origins.push(allowed);
+        self
+    }
+
+    /// Origins matching `pattern`; anchor it with `^` and `$` to match whole origins.
+    pub fn allow_origin_regex(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.origins.push(AllowedOrigin::Regex(Regex::new(pattern)?));
+        Ok(self)
+    }
+
+    pub fn allow_methods(mut self, methods: &[&str]) -> Self {
+        self.methods = methods.iter().map(|method| method.to_ascii_uppercase()).collect();
+        self
+    }
+
+    /// Request headers allowed beyond the CORS-safelisted ones; `*` allows any.
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.iter().map(|header| header.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Response headers scripts may read beyond the CORS-safelisted ones.
+    pub fn expose_headers(mut self, headers: &[&str]) -> Self {
+        self.exposed_headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    /// Lets origins allowed by name or pattern send cookies and credentials. Origins allowed only through `*`
+    /// never can, since that would let any site act as the user.
+    pub fn allow_credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed.matches(origin))
+    }
+
+    // Credentials are only allowed for origins matched by name or pattern, never for any origin through `*`.
+    fn allows_credentials(&self, origin: &str) -> bool {
+        self.credentials && self.origins.iter().any(|allowed| !matches!(allowed, AllowedOrigin::Any) && allowed.matches(origin))
+    }
+
+    // Whether every allowed origin gets `*`; otherwise the origin is echoed and the response varies by it.
+    fn is_wildcard(&self) -> bool {
+        let credentials_possible = self.credentials && self.origins.iter().any(|allowed| !matches!(allowed, AllowedOrigin::Any));
+        !credentials_possible && self.origins.iter().any(|allowed| matches!(allowed, AllowedOrigin::Any))
+    }
+
+    fn add_origin_headers(&self, response: &mut HttpResponse, origin: &str) {
+        if self.allows_credentials(origin) {
+            response.headers.insert("Access-Control-Allow-Origin", origin);
+            response.headers.insert("Access-Control-Allow-Credentials", "true");
+        } else if self.origins.iter().any(|allowed| matches!(allowed, AllowedOrigin::Any)) {
+            response.headers.insert("Access-Control-Allow-Origin", "*");
+        } else {
+            response.headers.insert("Access-Control-Allow-Origin", origin);
+        }
+    }
+
+    fn get_preflight_response(&self, origin: &str, method: &str, requested_headers: Option<&str>) -> HttpResponse {
+        let requested_headers: Vec<&str> = requested_headers.unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|header| !header.is_empty())
+            .collect();
+
+        let any_header = self.headers.iter().any(|header| header == "*");
+        let headers_allowed = any_header || requested_headers.iter()
+            .all(|requested| self.headers.iter().any(|header| header.eq_ignore_ascii_case(requested)));
+        let method_allowed = self.methods.iter().any(|allowed| allowed == method);
+
+        if !self.is_origin_allowed(origin) || !method_allowed || !headers_allowed {
+            let mut response = HttpResponse::error(403);
+            add_vary(&mut response, &["Origin", "Access-Control-Request-Method", "Access-Control-Request-Headers"]);
+            return response;
+        }
+
+        let mut response = HttpResponse::no_content();
+        self.add_origin_headers(&mut response, origin);
+        response.headers.insert("Access-Control-Allow-Methods", self.methods.join(", "));
+        if !requested_headers.is_empty() {
+            response.headers.insert("Access-Control-Allow-Headers", requested_headers.join(", "));
+        }
+        if let Some(max_age) = self.max_age {
+            response.headers.insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        add_vary(&mut response, &["Origin", "Access-Control-Request-Method", "Access-Control-Request-Headers"]);
+
+        response
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: &mut Request, next: Next) -> HttpResponse {
+        let Some(origin) = request.header("Origin").map(str::to_string) else {
+            let mut response = next.run(request);
+            if !self.is_wildcard() {
+                add_vary(&mut response, &["Origin"]);
+            }
+            return response;
+        };
+
+        if request.method == "OPTIONS" {
+            if let Some(method) = request.header("Access-Control-Request-Method") {
+                return self.get_preflight_response(&origin, method.trim(), request.header("Access-Control-Request-Headers"));
+            }
+        }
+
+        let mut response = next.run(request);
+        if self.is_origin_allowed(&origin) {
+            self.add_origin_headers(&mut response, &origin);
+            if !self.exposed_headers.is_empty() {
+                response.headers.insert("Access-Control-Expose-Headers", self.exposed_headers.join(", "));
+            }
+        }
+        if !self.is_wildcard() {
+            add_vary(&mut response, &["Origin"]);
+        }
+
+        response
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::HashMap, fs, path::PathBuf, sync::Arc};
+use crate::{http::{get_reason_phrase, split_media_type, Body, HttpResponse, Request}, middleware::{Middleware, Next}, template::{escape_html, Context, Templates}};
+
+// How much `accept` wants `media_type`: the quality of the most specific range matching it.
+fn get_quality(accept: &str, media_type: &str) -> f32 {
+    let (main_type, _) = media_type.split_once('/').unwrap_or((media_type, ""));
+
+    accept.split(',')
+        .filter_map(|range| {
+            let (range, parameters) = split_media_type(range);
+            let specificity = match range.split_once('/') {
+                _ if range == media_type => 2,
+                Some((range_type, "*")) if range_type == main_type => 1,
+                Some(("*", "*")) => 0,
+                _ => return None
+            };
+            let quality = parameters.iter()
+                .find(|(name, _)| name == "q")
+                .and_then(|(_, value)| value.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((specificity, quality))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map_or(0.0, |(_, quality)| quality)
+}
+
+/// Whether the client's `Accept` header prefers JSON to HTML; without one, HTML is assumed.
+pub fn prefers_json(request: &Request) -> bool {
+    request.header("Accept").is_some_and(|accept| get_quality(accept, "application/json") > get_quality(accept, "text/html"))
+}
+
+fn is_json(response: &HttpResponse) -> bool {
+    response.headers.get("Content-Type").is_some_and(|content_type| {
+        let (media_type, _) = split_media_type(content_type);
+        media_type == "application/json" || media_type.ends_with("+json")
+    })
+}
+
+/// The page used when no other is configured or a configured one cannot be loaded.
+pub fn get_default_error_page(status: u16) -> String {
+    let title = escape_html(format!("{status} {}", get_reason_phrase(status)).trim_end());
+    format!("<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"utf-8\">\n    <title>{title}</title>\n  </head>\n  <body>\n    <h1>{title}</h1>\n  </body>\n</html>\n")
+}
+
+impl HttpResponse {
+    /// An HTML response with the built-in error page for `status`.
+    pub fn error(status: u16) -> Self {
+        HttpResponse { default_body: true, ..HttpResponse::html(status, get_default_error_page(status)) }
+    }
+}
+
+pub enum ErrorPage {
+    /// An HTML file read on every use.
+    File(PathBuf),
+    /// A template rendered with `status`, `reason` and `path` besides the pages' context.
+    Template(Arc<Templates>, String)
+}
+
+/// Error pages by status code, with HTML or JSON chosen by the client's `Accept` header.
+///
+/// As middleware, replaces the body of 4xx and 5xx responses that have none or only the built-in
+/// page from `HttpResponse::error`, keeping their other headers. A status without a page of its own gets the default page, and when that
+/// is missing or fails too, the built-in one.
+#[derive(Default)]
+pub struct ErrorPages {
+    pages: HashMap<u16, ErrorPage>,
+    default_page: Option<ErrorPage>,
+    context: Context
+}
+
+impl ErrorPages {
+    pub fn new() -> Self {
+        ErrorPages::default()
+    }
+
+    pub fn page(mut self, status: u16, page: ErrorPage) -> Self {
+        self.pages.insert(status, page);
+        self
+    }
+
+    pub fn default_page(mut self, page: ErrorPage) -> Self {
+        self.default_page = Some(page);
+        self
+    }
+
+    /// Extra variables for template pages.
+    pub fn context(mut self, context: Context) -> Self {
+        self.context = context;
+        self
+    }
+
+    fn render(&self, page: &ErrorPage, request: &Request, status: u16) -> Option<Vec<u8>> {
+        match page {
+            ErrorPage::File(path) => fs::read(path)
+                .inspect_err(|error| println!("Failed to read error page {}: {error}", path.display()))
+                .ok(),
+            ErrorPage::Template(templates, name) => {
+                let context = self.context.clone()
+                    .insert("status", status as usize)
+                    .insert("reason", get_reason_phrase(status))
+                    .insert("path", request.path());
+                templates.render(name, context)
+                    .inspect_err(|error| println!("Failed to render error page {name}: {error:?}"))
+                    .ok()
+                    .map(String::into_bytes)
+            }
+        }
+    }
+
+    /// The error response for `status`, in the format the client prefers.
+    pub fn get_response(&self, request: &Request, status: u16) -> HttpResponse {
+        if prefers_json(request) {
+            return HttpResponse::json_error(status, get_reason_phrase(status));
+        }
+
+        let contents = self.pages.get(&status).and_then(|page| self.render(page, request, status))
+            .or_else(|| self.default_page.as_ref().and_then(|page| self.render(page, request, status)));
+
+        match contents {
+            Some(contents) => HttpResponse::html(status, contents),
+            None => HttpResponse::error(status)
+        }
+    }
+
+    /// Replaces the body of `response` with the error page for its status, if it is an error
+    /// without a body of its own.
+    pub fn apply(&self, request: &Request, mut response: HttpResponse) -> HttpResponse {
+        let is_empty = matches!(&response.body, Body::Bytes(body) if body.is_empty());
+        if response.status < 400 || !(response.default_body || is_empty) || is_json(&response) {
+            return response;
+        }
+
+        let error = self.get_response(request, response.status);
+        if let Some(content_type) = error.headers.get("Content-Type") {
+            response.headers.insert("Content-Type", content_type);
+        }
+        // A `HEAD` response carries the length of the body it would have had.
+        if let (true, Body::Bytes(body)) = (response.headers.contains("Content-Length"), &error.body) {
+            response.headers.insert("Content-Length", body.len().to_string());
+        }
+        response.body = error.body;
+        response.default_body = error.default_body;
+
+        response
+    }
+}
+
+impl Middleware for ErrorPages {
+    fn handle(&self, request: &mut Request, next: Next) -> HttpResponse {
+        let response = next.run(request);
+        self.apply(request, response)
+    }
+}
@@ -0,0 +1,236 @@
+use std::{io::{self, BufReader, Read, Write}, time::Duration};
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::PathBuf};
+use crate::{cgi::{get_body_length, get_environment, read_script_head}, http::{Body, HttpResponse, Request, Stream}, proxy::{self, get_gateway_error_response}};
+
+const VERSION: u8 = 1;
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+const RESPONDER: u16 = 1;
+// Each connection carries a single request.
+const REQUEST_ID: u16 = 1;
+const MAX_CONTENT_LENGTH: usize = 65535;
+
+enum Address {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf)
+}
+
+/// A FastCGI responder, such as PHP-FPM, that requests are passed to over a new connection each.
+pub struct FastCgi {
+    address: Address,
+    params: Vec<(String, String)>,
+    connect_timeout: Duration,
+    response_timeout: Duration
+}
+
+impl FastCgi {
+    /// A responder listening on `address`, such as `127.0.0.1:9000`, or on a Unix socket given as `unix:<path>`.
+    pub fn new(address: &str) -> Self {
+        let address = match address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => Address::Unix(path.into()),
+            _ => Address::Tcp(address.into())
+        };
+
+        FastCgi { address, params: Vec::new(), connect_timeout: Duration::from_secs(5), response_timeout: Duration::from_secs(30) }
+    }
+
+    /// Passes an extra parameter, such as `SCRIPT_FILENAME`, replacing one derived from the request.
+    pub fn param(mut self, name: &str, value: &str) -> Self {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Longest wait on any read from or write to the responder before responding with 504.
+    pub fn response_timeout(mut self, response_timeout: Duration) -> Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn Stream + Send>> {
+        match &self.address {
+            Address::Tcp(address) => {
+                let stream = proxy::connect(address, self.connect_timeout)?;
+                stream.set_read_timeout(Some(self.response_timeout))?;
+                stream.set_write_timeout(Some(self.response_timeout))?;
+                Ok(Box::new(stream))
+            },
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                let stream = UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(self.response_timeout))?;
+                stream.set_write_timeout(Some(self.response_timeout))?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match &self.address {
+            Address::Tcp(address) => address.clone(),
+            #[cfg(unix)]
+            Address::Unix(path) => format!("unix:{}", path.display())
+        }
+    }
+}
+
+fn write_record<W: Write + ?Sized>(writer: &mut W, kind: u8, content: &[u8]) -> io::Result<()> {
+    let [id_high, id_low] = REQUEST_ID.to_be_bytes();
+    let [length_high, length_low] = (content.len() as u16).to_be_bytes();
+    let padding = (8 - content.len() % 8) % 8;
+
+    writer.write_all(&[VERSION, kind, id_high, id_low, length_high, length_low, padding as u8, 0])?;
+    writer.write_all(content)?;
+    writer.write_all(&[0; 8][..padding])
+}
+
+// Writes `content` as a stream of records, followed by the empty record that ends the stream.
+fn write_stream<W: Write + ?Sized>(writer: &mut W, kind: u8, content: &[u8]) -> io::Result<()> {
+    for chunk in content.chunks(MAX_CONTENT_LENGTH) {
+        write_record(writer, kind, chunk)?;
+    }
+    write_record(writer, kind, &[])
+}
+
+fn encode_length(buffer: &mut Vec<u8>, length: usize) {
+    match length {
+        0..=127 => buffer.push(length as u8),
+        _ => buffer.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes())
+    }
+}
+
+fn encode_params(params: &[(String, String)]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for (name, value) in params {
+        encode_length(&mut buffer, name.len());
+        encode_length(&mut buffer, value.len());
+        buffer.extend_from_slice(name.as_bytes());
+        buffer.extend_from_slice(value.as_bytes());
+    }
+    buffer
+}
+
+fn send_request(fastcgi: &FastCgi, request: &mut Request, content_length: u64, stream: &mut dyn Stream) -> io::Result<()> {
+    let [role_high, role_low] = RESPONDER.to_be_bytes();
+    write_record(stream, BEGIN_REQUEST, &[role_high, role_low, 0, 0, 0, 0, 0, 0])?;
+
+    let mut params = get_environment(request, content_length);
+    for (name, value) in &fastcgi.params {
+        params.retain(|(existing, _)| existing != name);
+        params.push((name.clone(), value.clone()));
+    }
+    write_stream(stream, PARAMS, &encode_params(&params))?;
+
+    let mut body = (&mut request.body).take(content_length);
+    let mut buffer = vec![0; 32 * 1024];
+    loop {
+        match body.read(&mut buffer)? {
+            0 => break,
+            read => write_record(stream, STDIN, &buffer[..read])?
+        }
+    }
+    write_record(stream, STDIN, &[])?;
+
+    stream.flush()
+}
+
+// Reads the responder's standard output out of its records, logging its standard error, until the request ends.
+struct StdoutReader<R: Read> {
+    reader: R,
+    remaining: usize,
+    padding: usize,
+    done: bool
+}
+
+impl<R: Read> StdoutReader<R> {
+    fn skip(&mut self, length: usize) -> io::Result<Vec<u8>> {
+        let mut content = vec![0; length];
+        self.reader.read_exact(&mut content)?;
+        Ok(content)
+    }
+}
+
+impl<R: Read> Read for StdoutReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            if self.done || buf.is_empty() {
+                return Ok(0);
+            }
+
+            self.skip(self.padding)?;
+            let mut header = [0; 8];
+            self.reader.read_exact(&mut header)?;
+            let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            self.padding = header[6] as usize;
+
+            match header[1] {
+                STDOUT => self.remaining = length,
+                STDERR => {
+                    let message = self.skip(length)?;
+                    println!("FastCGI error output: {}", String::from_utf8_lossy(&message).trim_end());
+                },
+                END_REQUEST => {
+                    self.skip(length + self.padding)?;
+                    self.done = true;
+                },
+                _ => {
+                    self.skip(length)?;
+                }
+            }
+        }
+
+        let limit = buf.len().min(self.remaining);
+        let read = self.reader.read(&mut buf[..limit])?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "FastCGI record cut short"));
+        }
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+/// Passes `request` to the responder of `fastcgi` and streams its output back as the response.
+pub fn forward(fastcgi: &FastCgi, request: &mut Request) -> HttpResponse {
+    let content_length = match get_body_length(request) {
+        Ok(content_length) => content_length,
+        Err(response) => return response
+    };
+
+    let mut stream = match fastcgi.connect() {
+        Ok(stream) => stream,
+        Err(error) => {
+            println!("Failed to connect to FastCGI responder {}: {error}", fastcgi.describe());
+            return HttpResponse::error(502);
+        }
+    };
+
+    let response_head = send_request(fastcgi, request, content_length, stream.as_mut())
+        .map(|_| BufReader::new(StdoutReader { reader: BufReader::new(stream), remaining: 0, padding: 0, done: false }))
+        .and_then(|mut reader| read_script_head(&mut reader).map(|head| (head, reader)));
+
+    let ((status, headers), mut reader) = match response_head {
+        Ok(response_head) => response_head,
+        Err(error) => {
+            println!("FastCGI responder {} failed: {error}", fastcgi.describe());
+            return get_gateway_error_response(&error);
+        }
+    };
+
+    let body = Body::Stream(Box::new(move |writer| {
+        io::copy(&mut reader, writer)?;
+        Ok(())
+    }));
+
+    HttpResponse { status, headers, body, upgrade: None, default_body: false }
+}
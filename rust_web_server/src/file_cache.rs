@@ -0,0 +1,174 @@
+use std::{collections::{BTreeMap, HashMap}, fs, io, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}, time::SystemTime};
+use crate::{http::{Body, HttpResponse, Request}, mime::get_content_type, static_files::{self, resolve_path}};
+
+#[derive(Default)]
+pub struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64
+}
+
+impl CacheStats {
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Lookups that read the file from disk, because it was not cached or had changed.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions.load(Ordering::Relaxed)
+    }
+}
+
+pub struct CachedFile {
+    pub contents: Arc<[u8]>,
+    pub modified: SystemTime,
+    pub content_type: &'static str
+}
+
+struct Entry {
+    file: Arc<CachedFile>,
+    last_used: u64
+}
+
+#[derive(Default)]
+struct Entries {
+    files: HashMap<PathBuf, Entry>,
+    // Least recently used first, keyed by `Entry::last_used`.
+    recency: BTreeMap<u64, PathBuf>,
+    bytes: usize,
+    clock: u64
+}
+
+impl Entries {
+    fn touch(&mut self, path: &Path) {
+        self.clock += 1;
+        if let Some(entry) = self.files.get_mut(path) {
+            self.recency.remove(&entry.last_used);
+            entry.last_used = self.clock;
+            self.recency.insert(self.clock, path.to_path_buf());
+        }
+    }
+
+    fn remove(&mut self, path: &Path) -> Option<Entry> {
+        let entry = self.files.remove(path)?;
+        self.recency.remove(&entry.last_used);
+        self.bytes -= entry.file.contents.len();
+        Some(entry)
+    }
+}
+
+/// File contents and metadata kept in memory, shared by all workers.
+///
+/// Bounded by total bytes and number of files, evicting the least recently used first. Each
+/// lookup checks the file's modification time and size, so changed files are read again.
+/// Files over `max_entry_size` are never cached.
+pub struct FileCache {
+    max_bytes: usize,
+    max_entries: usize,
+    max_entry_size: usize,
+    entries: Mutex<Entries>,
+    stats: Arc<CacheStats>
+}
+
+impl FileCache {
+    pub fn new(max_bytes: usize, max_entries: usize) -> Self {
+        FileCache { max_bytes, max_entries, max_entry_size: 1024 * 1024, entries: Mutex::new(Entries::default()), stats: Arc::new(CacheStats::default()) }
+    }
+
+    /// Larger files are sent from disk by `serve`, which is cheaper than holding them in memory.
+    pub fn max_entry_size(mut self, max_entry_size: usize) -> Self {
+        self.max_entry_size = max_entry_size;
+        self
+    }
+
+    pub fn stats(&self) -> Arc<CacheStats> {
+        Arc::clone(&self.stats)
+    }
+
+    pub fn get(&self, path: &Path) -> io::Result<Arc<CachedFile>> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?;
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let cached = entries.files.get(path)
+                .filter(|entry| entry.file.modified == modified && entry.file.contents.len() as u64 == metadata.len())
+                .map(|entry| Arc::clone(&entry.file));
+
+            match cached {
+                Some(file) => {
+                    entries.touch(path);
+                    self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(file);
+                },
+                None => {
+                    entries.remove(path);
+                }
+            }
+        }
+
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+        let file = Arc::new(CachedFile { contents: fs::read(path)?.into(), modified, content_type: get_content_type(path) });
+        self.insert(path, Arc::clone(&file));
+
+        Ok(file)
+    }
+
+    fn insert(&self, path: &Path, file: Arc<CachedFile>) {
+        let size = file.contents.len();
+        if size > self.max_bytes || size > self.max_entry_size || self.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(path);
+        while entries.bytes + size > self.max_bytes || entries.files.len() >= self.max_entries {
+            let Some(oldest) = entries.recency.values().next().cloned() else {
+                break;
+            };
+            entries.remove(&oldest);
+            self.stats.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        entries.clock += 1;
+        let last_used = entries.clock;
+        entries.recency.insert(last_used, path.to_path_buf());
+        entries.files.insert(path.to_path_buf(), Entry { file, last_used });
+        entries.bytes += size;
+    }
+
+    /// Total size of the cached files.
+    pub fn size(&self) -> usize {
+        self.entries.lock().unwrap().bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Like `static_files::serve_file`, but from the cache; files too large to cache are still sent from disk.
+    /// Cached contents are shared with the response rather than copied.
+    pub fn serve(&self, root: &Path, request: &Request) -> Option<HttpResponse> {
+        if request.method != "GET" && request.method != "HEAD" {
+            return None;
+        }
+
+        let path = resolve_path(root, request.path())?;
+        if fs::metadata(&path).ok()?.len() > self.max_bytes.min(self.max_entry_size) as u64 {
+            return static_files::serve_file(root, request);
+        }
+        let file = self.get(&path).ok()?;
+
+        let mut response = HttpResponse::new(200, file.content_type, Vec::new());
+        response.body = Body::Shared(Arc::clone(&file.contents));
+        Some(response)
+    }
+}
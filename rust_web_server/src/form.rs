@@ -0,0 +1,59 @@
+use std::io::{self, Read};
+use crate::{http::{HttpResponse, Request}, url::Query};
+
+pub const DEFAULT_FORM_LIMIT: u64 = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FormError {
+    UnsupportedMediaType,
+    TooLarge,
+    Malformed(&'static str),
+    Io(io::Error)
+}
+
+impl From<io::Error> for FormError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::FileTooLarge => FormError::TooLarge,
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => FormError::Malformed("truncated or invalid body"),
+            _ => FormError::Io(error)
+        }
+    }
+}
+
+impl FormError {
+    pub fn get_response(&self) -> HttpResponse {
+        match self {
+            FormError::UnsupportedMediaType => HttpResponse::error(415),
+            FormError::TooLarge => HttpResponse::error(413),
+            FormError::Malformed(_) | FormError::Io(_) => HttpResponse::bad_request()
+        }
+    }
+}
+
+/// Reads at most `limit` bytes of body, failing with `TooLarge` beyond that.
+pub fn read_limited(request: &mut Request, limit: u64) -> Result<Vec<u8>, FormError> {
+    if request.content_length().is_some_and(|length| length > limit) {
+        return Err(FormError::TooLarge);
+    }
+
+    let mut body = Vec::new();
+    (&mut request.body).take(limit + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > limit {
+        return Err(FormError::TooLarge);
+    }
+
+    Ok(body)
+}
+
+/// Parses an `application/x-www-form-urlencoded` request body.
+pub fn read_form(request: &mut Request, limit: u64) -> Result<Query, FormError> {
+    if request.media_type().as_deref() != Some("application/x-www-form-urlencoded") {
+        return Err(FormError::UnsupportedMediaType);
+    }
+
+    let body = read_limited(request, limit)?;
+    let body = std::str::from_utf8(&body).map_err(|_| FormError::Malformed("form body is not UTF-8"))?;
+
+    Ok(Query::parse(body))
+}
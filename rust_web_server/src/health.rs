@@ -0,0 +1,43 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::http::HttpResponse;
+
+/// Liveness and readiness for `/healthz` and `/readyz` endpoints.
+///
+/// A server is ready from the start; shutting down its `ServerHandle` marks it not ready
+/// so load balancers stop sending it traffic while it drains.
+pub struct Health {
+    ready: AtomicBool
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health::new()
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Health { ready: AtomicBool::new(true) }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    /// 200 for as long as the process can answer at all.
+    pub fn get_liveness_response(&self) -> HttpResponse {
+        HttpResponse::new(200, "text/plain; charset=utf-8", "ok")
+    }
+
+    /// 200 while ready, 503 otherwise.
+    pub fn get_readiness_response(&self) -> HttpResponse {
+        match self.is_ready() {
+            true => HttpResponse::new(200, "text/plain; charset=utf-8", "ready"),
+            false => HttpResponse::new(503, "text/plain; charset=utf-8", "not ready")
+        }
+    }
+}
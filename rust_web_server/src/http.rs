@@ -0,0 +1,505 @@
+use std::{fs::File, io::{self, BufRead, Read, Write}, mem, net::{SocketAddr, TcpStream}, sync::{Arc, Mutex}, time::Duration};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use crate::url::{self, Query};
+
+/// Lets a reader stop waiting on a connection that stays idle.
+pub trait ReadTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl ReadTimeout for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl<S: ReadTimeout + ?Sized> ReadTimeout for &mut S {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+}
+
+pub trait Stream: Read + Write + ReadTimeout {}
+
+impl<T: Read + Write + ReadTimeout + ?Sized> Stream for T {}
+
+/// Takes over the connection once the response head has been written.
+pub type Upgrade = Box<dyn FnOnce(&mut dyn Stream) + Send>;
+
+/// Writes a response body of unknown length directly to the connection.
+pub type StreamBody = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// Bytes kept elsewhere too, such as in a cache, sent without copying them.
+    Shared(Arc<[u8]>),
+    Stream(StreamBody),
+    /// The next `u64` bytes of the file, from its current position.
+    File(File, u64)
+}
+
+/// The most a request or response head, or a trailer section, may take up.
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub enum RequestError {
+    ConnectionClosed,
+    Malformed,
+    /// The head went past `MAX_HEAD_SIZE`.
+    HeadTooLarge,
+    Io(io::Error)
+}
+
+impl From<io::Error> for RequestError {
+    fn from(error: io::Error) -> Self {
+        RequestError::Io(error)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers(Vec::new())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0.iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.0.push((name, value.into()));
+    }
+
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.push((name.into(), value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+pub struct Request<'a> {
+    pub method: String,
+    target: String,
+    path: String,
+    query: Query,
+    pub version: String,
+    pub headers: Headers,
+    pub remote_addr: Option<SocketAddr>,
+    pub secure: bool,
+    /// The path pattern of the route handling the request, set by `Router`.
+    pub route: Option<String>,
+    pub body: Box<dyn Read + 'a>,
+    pub(crate) trailers: Arc<Mutex<Headers>>
+}
+
+impl Request<'_> {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.header("Host")
+    }
+
+    /// The `Host` header without its port, lowercased.
+    pub fn host_name(&self) -> Option<String> {
+        self.host().map(|host| strip_port(host).to_ascii_lowercase())
+    }
+
+    /// The request target exactly as the client sent it.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Replaces the request target, keeping `path` and `query` in sync with it.
+    pub fn set_target(&mut self, target: &str) -> Result<(), RequestError> {
+        let (path, query) = parse_target(target).ok_or(RequestError::Malformed)?;
+        (self.target, self.path, self.query) = (target.into(), path, query);
+        Ok(())
+    }
+
+    /// The percent-decoded, normalized path of the target.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// The media type of the body without parameters, lowercased.
+    pub fn media_type(&self) -> Option<String> {
+        self.header("Content-Type").map(|content_type| split_media_type(content_type).0)
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.header("Content-Length").and_then(|length| length.parse().ok())
+    }
+
+    pub fn read_body(&mut self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        self.body.read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    /// The trailer fields of a chunked body, empty until the body has been read to the end.
+    pub fn trailers(&self) -> Headers {
+        self.trailers.lock().unwrap().clone()
+    }
+}
+
+/// Splits a `Content-Type` style value into its lowercased media type and parameters.
+pub fn split_media_type(value: &str) -> (String, Vec<(String, String)>) {
+    let mut parts = value.split(';');
+    let media_type = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    let parameters = parts
+        .filter_map(|parameter| parameter.split_once('='))
+        .map(|(name, value)| {
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value);
+            (name.trim().to_ascii_lowercase(), value.to_string())
+        })
+        .collect();
+
+    (media_type, parameters)
+}
+
+// Accepts origin-form targets, absolute-form targets and `*`.
+fn parse_target(target: &str) -> Option<(String, Query)> {
+    if target == "*" {
+        return Some((target.into(), Query::default()));
+    }
+
+    let origin_form = match target.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") => {
+            rest.find(['/', '?']).map_or("/", |index| &rest[index..])
+        },
+        _ => target
+    };
+
+    let (path, query) = origin_form.split_once('?').unwrap_or((origin_form, ""));
+    let path = match path {
+        "" => "/",
+        path if path.starts_with('/') => path,
+        _ => return None
+    };
+
+    Some((url::normalize_path(&url::percent_decode(path)?), Query::parse(query)))
+}
+
+pub fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host
+    }
+}
+
+/// Whether a comma-separated header value, such as `Connection`, lists `token`.
+pub(crate) fn has_token(value: Option<&str>, token: &str) -> bool {
+    value.is_some_and(|value| value.split(',').any(|item| item.trim().eq_ignore_ascii_case(token)))
+}
+
+pub(crate) fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, RequestError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    let trimmed_length = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(trimmed_length);
+    Ok(Some(line))
+}
+
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<Request<'static>, RequestError> {
+    let mut head = reader.take(MAX_HEAD_SIZE as u64);
+    match read_request_head(&mut head) {
+        Err(_) if head.limit() == 0 => Err(RequestError::HeadTooLarge),
+        result => result
+    }
+}
+
+fn read_request_head<R: BufRead>(reader: &mut R) -> Result<Request<'static>, RequestError> {
+    let request_line = read_line(reader)?.ok_or(RequestError::ConnectionClosed)?;
+
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) if !method.is_empty() && !target.is_empty() => (method, target, version),
+        _ => return Err(RequestError::Malformed)
+    };
+
+    let (path, query) = parse_target(target).ok_or(RequestError::Malformed)?;
+    let headers = read_headers(reader)?;
+
+    Ok(Request {
+        method: method.into(),
+        target: target.into(),
+        path,
+        query,
+        version: version.into(),
+        headers,
+        remote_addr: None,
+        secure: false,
+        route: None,
+        body: Box::new(io::empty()),
+        trailers: Arc::new(Mutex::new(Headers::new()))
+    })
+}
+
+pub(crate) fn read_headers<R: BufRead>(reader: &mut R) -> Result<Headers, RequestError> {
+    let mut reader = reader.take(MAX_HEAD_SIZE as u64);
+    let mut headers = Headers::new();
+    loop {
+        let line = match read_line(&mut reader)? {
+            Some(line) => line,
+            None if reader.limit() == 0 => return Err(RequestError::HeadTooLarge),
+            None => return Err(RequestError::Malformed)
+        };
+        if line.is_empty() {
+            return Ok(headers);
+        }
+
+        match line.split_once(':') {
+            Some((name, value)) if !name.is_empty() && !name.ends_with(' ') => headers.append(name, value.trim()),
+            _ => return Err(RequestError::Malformed)
+        }
+    }
+}
+
+pub(crate) fn read_status<R: BufRead>(reader: &mut R) -> io::Result<u16> {
+    let status_line = read_line(reader).map_err(into_io_error)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "peer closed the connection"))?;
+
+    status_line.split(' ').nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed status line"))
+}
+
+pub(crate) fn into_io_error(error: RequestError) -> io::Error {
+    match error {
+        RequestError::Io(error) => error,
+        _ => io::Error::new(io::ErrorKind::InvalidData, "malformed response head")
+    }
+}
+
+/// Reads a response's status and headers, skipping interim 1xx responses. `101 Switching Protocols`
+/// is final, since the connection changes protocol after it.
+pub fn read_response_head<R: BufRead>(reader: &mut R) -> io::Result<(u16, Headers)> {
+    loop {
+        let status = read_status(reader)?;
+        let headers = read_headers(reader).map_err(into_io_error)?;
+
+        if !(100..200).contains(&status) || status == 101 {
+            return Ok((status, headers));
+        }
+    }
+}
+
+/// Copies `length` bytes of `file` through a buffer, failing if the file turns out shorter.
+pub(crate) fn copy_file<W: Write + ?Sized>(file: &mut File, length: u64, writer: &mut W) -> io::Result<()> {
+    let copied = io::copy(&mut file.take(length), writer)?;
+    match copied < length {
+        true => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shorter than its Content-Length")),
+        false => Ok(())
+    }
+}
+
+pub fn get_reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        417 => "Expectation Failed",
+        422 => "Unprocessable Content",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => ""
+    }
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Body,
+    pub upgrade: Option<Upgrade>,
+    /// Set by `HttpResponse::error`: the body is the built-in error page, which `ErrorPages` replaces.
+    pub default_body: bool
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        let mut headers = Headers::new();
+        headers.insert("Content-Type", content_type);
+
+        HttpResponse { status, headers, body: Body::Bytes(body.into()), upgrade: None, default_body: false }
+    }
+
+    pub fn html(status: u16, contents: impl Into<Vec<u8>>) -> Self {
+        HttpResponse::new(status, "text/html", contents)
+    }
+
+    pub fn ok(contents: impl Into<Vec<u8>>) -> Self {
+        HttpResponse::html(200, contents)
+    }
+
+    pub fn not_found(contents: impl Into<Vec<u8>>) -> Self {
+        HttpResponse::html(404, contents)
+    }
+
+    pub fn no_content() -> Self {
+        HttpResponse { status: 204, headers: Headers::new(), body: Body::Bytes(Vec::new()), upgrade: None, default_body: false }
+    }
+
+    pub fn bad_request() -> Self {
+        HttpResponse::error(400)
+    }
+
+    pub fn internal_server_error() -> Self {
+        HttpResponse::error(500)
+    }
+
+    pub fn redirect(status: u16, location: &str) -> Self {
+        HttpResponse::html(status, "").with_header("Location", location)
+    }
+
+    /// The body is written by `stream` and sent chunked to HTTP/1.1 clients, or delimited by
+    /// closing the connection for older ones, unless a `Content-Length` header is set explicitly.
+    pub fn stream(status: u16, content_type: &str, stream: StreamBody) -> Self {
+        let mut headers = Headers::new();
+        headers.insert("Content-Type", content_type);
+
+        HttpResponse { status, headers, body: Body::Stream(stream), upgrade: None, default_body: false }
+    }
+
+    /// Sends the rest of `file`, zero-copy where the connection supports it.
+    pub fn file(status: u16, content_type: &str, file: File) -> io::Result<Self> {
+        let length = file.metadata()?.len();
+        let mut response = HttpResponse::new(status, content_type, Vec::new());
+        response.body = Body::File(file, length);
+        Ok(response)
+    }
+
+    pub fn switching_protocols(protocol: &str, upgrade: Upgrade) -> Self {
+        let mut headers = Headers::new();
+        headers.insert("Upgrade", protocol);
+        headers.insert("Connection", "Upgrade");
+
+        HttpResponse { status: 101, headers, body: Body::Bytes(Vec::new()), upgrade: Some(upgrade), default_body: false }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// The answer to a `HEAD` request: the same head, including the length the body would have had, without the body.
+    pub(crate) fn without_body(mut self) -> Self {
+        let length = match mem::replace(&mut self.body, Body::Bytes(Vec::new())) {
+            Body::Bytes(body) => Some(body.len() as u64),
+            Body::Shared(body) => Some(body.len() as u64),
+            Body::File(_, length) => Some(length),
+            Body::Stream(_) => None
+        };
+        if let Some(length) = length.filter(|_| self.status >= 200 && self.status != 204 && self.status != 304) {
+            if !self.headers.contains("Content-Length") {
+                self.headers.insert("Content-Length", length.to_string());
+            }
+        }
+        self
+    }
+
+    pub(crate) fn write_head<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let status = self.status;
+        let reason = get_reason_phrase(status);
+        let mut head = format!("HTTP/1.1 {status} {reason}\r\n");
+        let content_length = match &self.body {
+            Body::Bytes(body) => Some(body.len() as u64),
+            Body::Shared(body) => Some(body.len() as u64),
+            Body::File(_, length) => Some(*length),
+            Body::Stream(_) => None
+        };
+        if let Some(content_length) = content_length.filter(|_| !self.headers.contains("Content-Length")) {
+            if status >= 200 && status != 204 && status != 304 {
+                head.push_str(&format!("Content-Length: {content_length}\r\n"));
+            }
+        }
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+
+        writer.write_all(head.as_bytes())
+    }
+
+    pub fn write_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        self.write_head(writer)?;
+        match self.body {
+            Body::Bytes(body) => writer.write_all(&body)?,
+            Body::Shared(body) => writer.write_all(&body)?,
+            Body::Stream(stream) => {
+                writer.flush()?;
+                stream(writer)?;
+            },
+            Body::File(mut file, length) => copy_file(&mut file, length, writer)?
+        }
+        writer.flush()
+    }
+}
@@ -0,0 +1,87 @@
+use std::io;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use crate::{form::{read_limited, FormError}, http::{get_reason_phrase, split_media_type, Body, HttpResponse, Request}, middleware::{Middleware, Next}};
+
+pub const DEFAULT_JSON_LIMIT: u64 = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum JsonError {
+    UnsupportedMediaType,
+    TooLarge,
+    Malformed(String),
+    Io(io::Error)
+}
+
+impl From<FormError> for JsonError {
+    fn from(error: FormError) -> Self {
+        match error {
+            FormError::UnsupportedMediaType => JsonError::UnsupportedMediaType,
+            FormError::TooLarge => JsonError::TooLarge,
+            FormError::Malformed(message) => JsonError::Malformed(message.to_string()),
+            FormError::Io(error) => JsonError::Io(error)
+        }
+    }
+}
+
+impl JsonError {
+    pub fn get_response(&self) -> HttpResponse {
+        match self {
+            JsonError::UnsupportedMediaType => HttpResponse::json_error(415, "expected an application/json body"),
+            JsonError::TooLarge => HttpResponse::json_error(413, "request body too large"),
+            JsonError::Malformed(message) => HttpResponse::json_error(400, message),
+            JsonError::Io(_) => HttpResponse::json_error(400, "could not read request body")
+        }
+    }
+}
+
+fn is_json_media_type(media_type: &str) -> bool {
+    media_type == "application/json" || media_type.starts_with("application/") && media_type.ends_with("+json")
+}
+
+/// Deserializes a JSON request body of at most `limit` bytes.
+pub fn read_json<T: DeserializeOwned>(request: &mut Request, limit: u64) -> Result<T, JsonError> {
+    if !request.media_type().is_some_and(|media_type| is_json_media_type(&media_type)) {
+        return Err(JsonError::UnsupportedMediaType);
+    }
+
+    let body = read_limited(request, limit)?;
+    serde_json::from_slice(&body).map_err(|error| JsonError::Malformed(format!("invalid JSON: {error}")))
+}
+
+impl HttpResponse {
+    /// Serializes `value` as the body; a value that cannot be serialized gives a 500.
+    pub fn json<T: Serialize + ?Sized>(status: u16, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => HttpResponse::new(status, "application/json", body),
+            Err(_) => HttpResponse::json_error(500, get_reason_phrase(500))
+        }
+    }
+
+    /// An error body of the form `{"error": {"status": 404, "message": "..."}}`.
+    pub fn json_error(status: u16, message: &str) -> Self {
+        let body = json!({ "error": { "status": status, "message": message } });
+        HttpResponse::new(status, "application/json", body.to_string())
+    }
+}
+
+/// Replaces non-JSON 4xx and 5xx bodies with `HttpResponse::json_error`, keeping the headers.
+pub struct JsonErrors;
+
+impl Middleware for JsonErrors {
+    fn handle(&self, request: &mut Request, next: Next) -> HttpResponse {
+        let mut response = next.run(request);
+        let is_json = response.headers.get("Content-Type").is_some_and(|content_type| {
+            let (media_type, _) = split_media_type(content_type);
+            is_json_media_type(&media_type)
+        });
+
+        if response.status >= 400 && !is_json && matches!(response.body, Body::Bytes(_)) {
+            let error = HttpResponse::json_error(response.status, get_reason_phrase(response.status));
+            response.headers.insert("Content-Type", "application/json");
+            response.body = error.body;
+        }
+
+        response
+    }
+}
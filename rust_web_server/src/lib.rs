@@ -1,4 +1,40 @@
-use std::{sync::{mpsc, Arc, Mutex}, thread};
+pub mod access;
+pub mod assets;
+pub mod auth;
+pub mod cgi;
+pub mod chunked;
+pub mod cookie;
+pub mod cors;
+pub mod error_pages;
+pub mod fastcgi;
+pub mod file_cache;
+pub mod form;
+pub mod http;
+pub mod health;
+pub mod json;
+pub mod listener;
+pub mod metrics;
+pub mod middleware;
+pub mod mime;
+pub mod multipart;
+pub mod proxy;
+pub mod ratelimit;
+#[cfg(unix)]
+pub mod reactor;
+pub mod router;
+pub mod sendfile;
+pub mod server;
+pub mod session;
+pub mod sse;
+pub mod static_files;
+pub mod template;
+pub mod testing;
+pub mod tls;
+pub mod url;
+pub mod vhost;
+pub mod websocket;
+
+use std::{sync::{atomic::{AtomicUsize, Ordering}, mpsc, Arc, Mutex}, thread};
 pub enum PoolCreationError {
     InvalidThreadCount
 }
@@ -10,16 +46,42 @@ struct Worker {
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// Live counts for a `ThreadPool`, shared with whoever reports on it.
+pub struct PoolStats {
+    size: usize,
+    queued: AtomicUsize,
+    busy: AtomicUsize
+}
+
+impl PoolStats {
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Jobs waiting for a free worker.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Workers currently running a job.
+    pub fn busy(&self) -> usize {
+        self.busy.load(Ordering::Relaxed)
+    }
+}
+
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>, stats: Arc<PoolStats>) -> Self {
         let handle = thread::spawn(move|| {
             loop {
                 let message = receiver.lock().unwrap().recv();
 
                 match message {
                     Ok(job) => {
+                        stats.queued.fetch_sub(1, Ordering::Relaxed);
+                        stats.busy.fetch_add(1, Ordering::Relaxed);
                         println!("Worker {id} got a job; executing.");
                         job();        
+                        stats.busy.fetch_sub(1, Ordering::Relaxed);
                     },
                     Err(_) => { 
                         println!("Worker {id} disconnected; shutting down.");
@@ -49,7 +111,8 @@ impl Drop for ThreadPool {
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>
+    sender: Option<mpsc::Sender<Job>>,
+    stats: Arc<PoolStats>
 }
 
 impl ThreadPool {
@@ -61,17 +124,24 @@ impl ThreadPool {
         let (sender, receiver) = mpsc::channel();
         let receiver = Arc::new(Mutex::new(receiver));
 
+        let stats = Arc::new(PoolStats { size, queued: AtomicUsize::new(0), busy: AtomicUsize::new(0) });
+
         let workers = (0..size).map(|id| {
-            Worker::new(id, Arc::clone(&receiver))
+            Worker::new(id, Arc::clone(&receiver), Arc::clone(&stats))
         }).collect();
 
-        Ok(ThreadPool { workers, sender: Some(sender) })
+        Ok(ThreadPool { workers, sender: Some(sender), stats })
+    }
+
+    pub fn stats(&self) -> Arc<PoolStats> {
+        Arc::clone(&self.stats)
     }
 
     pub fn execute<F>(&self, f: F) where F: FnOnce() + Send + 'static {
         let job = Box::new(f);
 
         if let Some(sender) = self.sender.as_ref() {
+            self.stats.queued.fetch_add(1, Ordering::Relaxed);
             sender.send(job).unwrap();
         }
     }
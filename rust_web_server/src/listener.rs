@@ -0,0 +1,135 @@
+use std::{io, net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs}};
+#[cfg(unix)]
+use std::{env, fs, os::{fd::{FromRawFd, RawFd}, unix::{fs::{FileTypeExt, PermissionsExt}, net::{UnixListener, UnixStream}}}, path::Path, process};
+use socket2::{Domain, Socket, Type};
+
+/// A socket the server accepts connections on.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener)
+}
+
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream)
+}
+
+const BACKLOG: i32 = 1024;
+
+impl Listener {
+    /// Binds the first address `addr` resolves to. IPv6 addresses also accept IPv4 connections,
+    /// so `[::]:80` listens on both.
+    pub fn bind_tcp(addr: impl ToSocketAddrs) -> io::Result<Listener> {
+        let addr = addr.to_socket_addrs()?.next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing"))?;
+
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(false)?;
+        }
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(BACKLOG)?;
+
+        Ok(Listener::Tcp(socket.into()))
+    }
+
+    /// Binds a Unix domain socket at `path` with permissions `mode`, such as `0o660`,
+    /// replacing a socket file left behind by an earlier run.
+    #[cfg(unix)]
+    pub fn bind_unix(path: impl AsRef<Path>, mode: u32) -> io::Result<Listener> {
+        let path = path.as_ref();
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+
+        Ok(Listener::Unix(listener))
+    }
+
+    /// Listening sockets passed by systemd-style socket activation, in the order they were
+    /// configured; empty when `LISTEN_PID` is not this process. It clears the `LISTEN_*`
+    /// variables, so call it before starting any threads.
+    #[cfg(unix)]
+    pub fn from_env() -> io::Result<Vec<Listener>> {
+        // The first file descriptor passed, after stdin, stdout and stderr.
+        const LISTEN_FDS_START: RawFd = 3;
+
+        if env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) != Some(process::id()) {
+            return Ok(Vec::new());
+        }
+        let count = env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<RawFd>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "LISTEN_FDS is missing or invalid"))?;
+
+        // Child processes must not think the sockets were passed to them.
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(name);
+        }
+
+        (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                // The descriptors were passed to this process to own.
+                let socket = unsafe { Socket::from_raw_fd(fd) };
+                socket.set_cloexec(true)?;
+                if socket.r#type()? != Type::STREAM {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("file descriptor {fd} is not a stream socket")));
+                }
+
+                Ok(match socket.local_addr()?.is_unix() {
+                    true => Listener::Unix(socket.into()),
+                    false => Listener::Tcp(socket.into())
+                })
+            })
+            .collect()
+    }
+
+    /// The bound address, or `None` for Unix domain sockets.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(_) => None
+        }
+    }
+
+    /// Connects to the listener, so a thread blocked accepting on it returns.
+    pub fn wake(&self) {
+        match self {
+            Listener::Tcp(listener) => {
+                if let Ok(addr) = listener.local_addr() {
+                    wake_tcp_listener(addr);
+                }
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                if let Some(path) = listener.local_addr().ok().and_then(|addr| addr.as_pathname().map(Path::to_path_buf)) {
+                    let _ = UnixStream::connect(path);
+                }
+            }
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Connection::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Connection::Unix(stream))
+        }
+    }
+}
+
+/// Connects to a TCP listener bound to `addr`, going through loopback when it is bound to every address.
+pub fn wake_tcp_listener(mut addr: SocketAddr) {
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into()
+        });
+    }
+    let _ = TcpStream::connect(addr);
+}
@@ -1,90 +1,313 @@
-use std::{fs, io::{BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, thread, time::Duration};
-use rust_web_server::ThreadPool;
+use std::{env, io, net::TcpListener, sync::Arc, thread, time::Duration};
+#[cfg(unix)]
+use std::{process, sync::atomic::{AtomicBool, Ordering}, time::Instant};
+use rust_web_server::{assets::Assets, cgi::Cgi, error_pages::{ErrorPage, ErrorPages}, fastcgi::FastCgi, file_cache::FileCache, health::Health, http::{HttpResponse, Request}, listener::{wake_tcp_listener, Listener}, metrics::Metrics, middleware::Logger, router::Router, server::Server, sse::{Event, EventStream}, template::{Context, Templates}, tls::{TlsConfig, TlsError}, vhost::{Site, VirtualHosts}, websocket::{Message, WebSocket}, ThreadPool};
+#[cfg(unix)]
+use rust_web_server::reactor::Reactor;
 
-fn get_request(mut stream: &TcpStream) -> Vec<String> {
-    let buf_reader = BufReader::new(&mut stream);
+// --dev reads html/ from the source tree on every request instead of using the embedded copy.
+fn get_assets(args: &[String]) -> Assets {
+    match args.iter().any(|arg| arg == "--dev") {
+        true => Assets::Disk(concat!(env!("CARGO_MANIFEST_DIR"), "/html").into()),
+        false => Assets::Embedded
+    }
+}
+
+fn get_context(request: &Request) -> Context {
+    Context::new()
+        .insert("path", request.path())
+        .insert("version", env!("CARGO_PKG_VERSION"))
+}
 
-    buf_reader
-        .lines()
-        .map_while(|result| result.ok())
-        .take_while(|line| !line.is_empty())
-        .collect()
+// 404.html for missing pages and error.html for every other error, in every site.
+fn get_error_pages(templates: &Arc<Templates>) -> ErrorPages {
+    ErrorPages::new()
+        .page(404, ErrorPage::Template(Arc::clone(templates), "404.html".into()))
+        .default_page(ErrorPage::Template(Arc::clone(templates), "error.html".into()))
+        .context(Context::new().insert("version", env!("CARGO_PKG_VERSION")))
 }
 
-enum HttpResponse
-{
-    Ok(String),
-    BadRequest(String),
-    InternalServerError(String),
-    NotFound(String)
+fn get_hello_response(templates: &Templates, request: &Request) -> HttpResponse {
+    thread::sleep(Duration::from_secs(5));
+
+    templates.response(200, "hello.html", get_context(request))
 }
 
-impl HttpResponse {
-    fn get_response_string(&self) -> String {
-        let (status_line, contents) = match self {
-            HttpResponse::Ok(contents) => ("HTTP/1.1 200 OK", contents),
-            HttpResponse::BadRequest(contents) => ("HTTP/1.1 400 Bad Request", contents),
-            HttpResponse::InternalServerError(contents) => ("HTTP/1.1 500 Internal Server Error", contents),
-            HttpResponse::NotFound(contents) => ("HTTP/1.1 404 Not Found", contents),
+fn echo(websocket: &mut WebSocket) {
+    while let Ok(message) = websocket.recv() {
+        let result = match message {
+            Message::Text(_) | Message::Binary(_) => websocket.send(message),
+            Message::Close(_) => break,
+            _ => Ok(())
         };
 
-        let length = contents.len();
-        format!("{status_line}\r\nContent-Length: {length}\r\nContent-Type: text/html\r\n\r\n{contents}")
+        if result.is_err() {
+            break;
+        }
     }
 }
 
-fn get_file_content(file_name: &str) -> Option<String> {
-    let exe_path = std::env::current_exe().ok()?;
-    let mut assets_path = exe_path.parent()?.to_path_buf();
-    assets_path.push(file_name);
+fn count(events: &mut EventStream) -> std::io::Result<()> {
+    let start = events.last_event_id().and_then(|id| id.parse::<u64>().ok()).map_or(0, |id| id + 1);
 
-    fs::read_to_string(assets_path).ok()
+    for id in start.. {
+        events.send(&Event::new(&id.to_string()).id(&id.to_string()).retry(Duration::from_secs(3)))?;
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    Ok(())
 }
 
-fn get_not_found_response() ->  HttpResponse {
-    match get_file_content("404.html") {
-        Some(content) => HttpResponse::NotFound(content),
-        _ => get_internal_server_error_response()
+// --site <host name> <document root> serves a directory for another host name.
+fn get_virtual_hosts(args: &[String], default_site: Site, templates: &Arc<Templates>, file_cache: &Arc<FileCache>) -> VirtualHosts {
+    let mut virtual_hosts = VirtualHosts::new(default_site);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg != "--site" {
+            continue;
+        }
+
+        if let (Some(host_name), Some(document_root)) = (args.next(), args.next()) {
+            let site = Site::new(Router::new())
+                .name(host_name)
+                .document_root(document_root)
+                .file_cache(Arc::clone(file_cache))
+                .error_pages(get_error_pages(templates));
+            virtual_hosts = virtual_hosts.site(site);
+        }
     }
+
+    virtual_hosts
 }
 
-fn get_bad_request_response() -> HttpResponse {
-    HttpResponse::BadRequest("Bad Request".into())
-} 
+// --cgi <path> <program> runs a CGI program for requests to a path such as /cgi-bin/app/*, and
+// --fastcgi <path> <address> passes them to a FastCGI responder at <host>:<port> or unix:<path>.
+fn add_gateways(mut router: Router, args: &[String]) -> Router {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cgi" => {
+                if let (Some(path), Some(program)) = (args.next(), args.next()) {
+                    router = router.cgi(path, Arc::new(Cgi::new(program)));
+                }
+            },
+            "--fastcgi" => {
+                if let (Some(path), Some(address)) = (args.next(), args.next()) {
+                    router = router.fastcgi(path, Arc::new(FastCgi::new(address)));
+                }
+            },
+            _ => {}
+        }
+    }
 
-fn get_internal_server_error_response() -> HttpResponse {
-    HttpResponse::InternalServerError("Internal Error".into())
+    router
 }
 
-fn process_request(http_request: &[String], path: &str) -> HttpResponse {
-    let request_line = match http_request.first() {
-        Some(request_line) if request_line.starts_with("GET") && request_line.ends_with("HTTP/1.1") => request_line,
-        _ => return get_bad_request_response() 
-    };
+// --tls-cert <cert.pem> --tls-key <key.pem> sets the default certificate,
+// --sni-cert <server name> <cert.pem> <key.pem> adds a certificate chosen by SNI,
+// and --tls-listen <address> sets where HTTPS is served (127.0.0.1:7879 by default).
+fn get_tls_config(args: &[String]) -> Result<Option<TlsConfig>, TlsError> {
+    let mut tls_config: Option<TlsConfig> = None;
+    let (mut cert_path, mut key_path) = (None, None);
 
-    if !request_line.eq_ignore_ascii_case(&format!("GET {path} HTTP/1.1")) {
-        return get_not_found_response();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tls-cert" => cert_path = args.next(),
+            "--tls-key" => key_path = args.next(),
+            "--sni-cert" => {
+                if let (Some(server_name), Some(cert), Some(key)) = (args.next(), args.next(), args.next()) {
+                    tls_config = Some(tls_config.unwrap_or_default().certificate(server_name, cert, key)?);
+                }
+            },
+            _ => {}
+        }
     }
 
-    thread::sleep(Duration::from_secs(5));
+    if let (Some(cert), Some(key)) = (cert_path, key_path) {
+        tls_config = Some(tls_config.unwrap_or_default().default_certificate(cert, key)?);
+    }
+
+    Ok(tls_config)
+}
+
+// --unix-mode <octal> sets the permissions of Unix sockets, 660 by default.
+#[cfg(unix)]
+fn get_unix_mode(args: &[String]) -> io::Result<u32> {
+    match args.iter().position(|arg| arg == "--unix-mode").and_then(|index| args.get(index + 1)) {
+        Some(mode) => u32::from_str_radix(mode, 8).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "--unix-mode must be octal")),
+        None => Ok(0o660)
+    }
+}
+
+// --listen <address> or --listen unix:<path> adds a listener to the sockets passed by systemd, and
+// without any listener at all the server listens on 127.0.0.1:7878.
+fn get_listeners(args: &[String], mut listeners: Vec<Listener>) -> io::Result<Vec<Listener>> {
+    let mut options = args.iter();
+    while let Some(option) = options.next() {
+        if option != "--listen" {
+            continue;
+        }
 
-    match get_file_content("hello.html") {
-        Some(contents) => HttpResponse::Ok(contents),
-        _ => get_internal_server_error_response()
+        if let Some(address) = options.next() {
+            let listener = match address.strip_prefix("unix:") {
+                #[cfg(unix)]
+                Some(path) => Listener::bind_unix(path, get_unix_mode(args)?)?,
+                #[cfg(not(unix))]
+                Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are only available on Unix")),
+                None => Listener::bind_tcp(address.as_str())?
+            };
+            listeners.push(listener);
+        }
+    }
+
+    if listeners.is_empty() {
+        listeners.push(Listener::bind_tcp("127.0.0.1:7878")?);
+    }
+
+    Ok(listeners)
+}
+
+// --threads <n> sets the number of workers in the thread pool, 4 by default.
+fn get_thread_count(args: &[String]) -> usize {
+    match args.iter().position(|arg| arg == "--threads").and_then(|index| args.get(index + 1)) {
+        Some(count) => count.parse().expect("--threads must be a number"),
+        None => 4
     }
 }
 
+#[cfg(unix)]
+static TERMINATING: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_terminate(_signal: libc::c_int) {
+    TERMINATING.store(true, Ordering::SeqCst);
+}
+
+// On SIGTERM the server reports not ready, keeps serving for --drain <seconds> (5 by default) so load
+// balancers can stop sending it traffic, then stops accepting and exits once open connections have
+// finished, or after 30 seconds. `wake` unblocks the threads accepting connections.
+#[cfg(unix)]
+fn shut_down_on_sigterm(args: &[String], server: Arc<Server>, health: Arc<Health>, metrics: Arc<Metrics>, wake: impl FnOnce() + Send + 'static) -> thread::JoinHandle<()> {
+    let drain = match args.iter().position(|arg| arg == "--drain").and_then(|index| args.get(index + 1)) {
+        Some(seconds) => Duration::from_secs(seconds.parse().expect("--drain must be a number of seconds")),
+        None => Duration::from_secs(5)
+    };
+    unsafe { libc::signal(libc::SIGTERM, on_terminate as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+
+    thread::spawn(move || {
+        while !TERMINATING.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        println!("Received SIGTERM, draining for {drain:?}.");
+        health.set_ready(false);
+        thread::sleep(drain);
+        server.stop_accepting();
+        wake();
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while metrics.open_connections() > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(100));
+        }
+        println!("Shutting down.");
+        process::exit(0);
+    })
+}
+
 fn main() {
-    if let Ok(thread_pool) = ThreadPool::new(4) {
-        let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+    // Taken before any thread starts, since it clears the LISTEN_* variables from the environment.
+    #[cfg(unix)]
+    let activated_listeners = Listener::from_env().expect("Failed to use the sockets passed by the service manager");
+    #[cfg(not(unix))]
+    let activated_listeners = Vec::new();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Ok(thread_pool) = ThreadPool::new(get_thread_count(&args)) {
+        let templates = Arc::new(Templates::from_assets(get_assets(&args)));
+        let hello_templates = Arc::clone(&templates);
+        // Document roots of all sites share 64 MiB for up to 1024 files.
+        let file_cache = Arc::new(FileCache::new(64 * 1024 * 1024, 1024));
+        let metrics = Arc::new(Metrics::new().thread_pool(thread_pool.stats()).file_cache(file_cache.stats()));
+        let health = Arc::new(Health::new());
+        let (metrics_route, liveness, readiness) = (Arc::clone(&metrics), Arc::clone(&health), Arc::clone(&health));
+
+        let router = Router::new()
+            .get("/", move |request| get_hello_response(&hello_templates, request))
+            .get("/metrics", move |_| metrics_route.get_response())
+            .get("/healthz", move |_| liveness.get_liveness_response())
+            .get("/readyz", move |_| readiness.get_readiness_response())
+            .websocket("/echo", echo)
+            .event_stream("/count", count);
+        let router = add_gateways(router, &args);
+        let default_site = Site::new(router).error_pages(get_error_pages(&templates));
+        let virtual_hosts = get_virtual_hosts(&args, default_site, &templates, &file_cache);
+        let mut server = Server::new(thread_pool, move |request| virtual_hosts.handle(request))
+            .with_middleware(Logger)
+            .with_metrics(Arc::clone(&metrics))
+            .with_health(Arc::clone(&health));
+        let mut tls_listener = None;
+
+        if let Some(tls_config) = get_tls_config(&args).expect("Failed to load TLS configuration") {
+            let address = match args.iter().position(|arg| arg == "--tls-listen").and_then(|index| args.get(index + 1)) {
+                Some(address) => address.as_str(),
+                None => "127.0.0.1:7879"
+            };
+            let listener = TcpListener::bind(address).expect("Failed to listen for TLS connections");
+            let https_port = listener.local_addr().unwrap().port();
+
+            server = server
+                .with_tls(tls_config.build().expect("Failed to build TLS configuration"))
+                .redirect_to_https(https_port);
+            tls_listener = Some(listener);
+        }
+
+        let server = Arc::new(server);
+        let mut wakers: Vec<Box<dyn Fn() + Send>> = Vec::new();
+
+        if let Some(listener) = tls_listener {
+            let server = Arc::clone(&server);
+            let address = listener.local_addr().unwrap();
+            wakers.push(Box::new(move || wake_tcp_listener(address)));
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    if !server.is_accepting() {
+                        return;
+                    }
+                    server.handle_tls_connection(stream);
+                }
+            });
+        }
 
-        for mut stream in listener.incoming().take(4).flatten() {
-            thread_pool.execute(move|| {
-                let http_request = get_request(&stream);
+        let use_reactor = args.iter().any(|arg| arg == "--reactor");
+        let listeners: Vec<_> = get_listeners(&args, activated_listeners).expect("Failed to listen")
+            .into_iter()
+            .map(|listener| {
+                let server = Arc::clone(&server);
+                match listener {
+                    // --reactor waits for requests with epoll instead of tying up a worker per connection.
+                    #[cfg(unix)]
+                    Listener::Tcp(listener) if use_reactor => thread::spawn(move || Reactor::new().run(&server, listener).expect("Reactor failed")),
+                    listener => {
+                        let listener = Arc::new(listener);
+                        let waker = Arc::clone(&listener);
+                        wakers.push(Box::new(move || waker.wake()));
+                        thread::spawn(move || server.serve(&listener))
+                    }
+                }
+            })
+            .collect();
+        #[cfg(unix)]
+        let shutdown = shut_down_on_sigterm(&args, Arc::clone(&server), health, metrics, move || wakers.iter().for_each(|wake| wake()));
 
-                let response = process_request(&http_request, "/");
-                stream.write_all(response.get_response_string().as_bytes()).unwrap();    
-                });
-        }    
+        for listener in listeners {
+            let _ = listener.join();
+        }
+        // The listeners only stop for SIGTERM, and the process exits once the connections are done.
+        #[cfg(unix)]
+        if TERMINATING.load(Ordering::SeqCst) {
+            let _ = shutdown.join();
+        }
     }
 }
@@ -0,0 +1,163 @@
+use std::{collections::BTreeMap, fmt::Write, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc, Mutex}, time::Duration};
+use crate::{file_cache::CacheStats, http::HttpResponse, PoolStats};
+
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS", "CONNECT", "TRACE"];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct RequestMetrics {
+    // (method, route, status)
+    counts: BTreeMap<(&'static str, String, u16), u64>,
+    latencies: BTreeMap<String, Histogram>
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn write_header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {name} {help}");
+    let _ = writeln!(output, "# TYPE {name} {kind}");
+}
+
+/// Request, connection and thread pool metrics for a server, rendered in the Prometheus text format.
+///
+/// Requests are labelled with the pattern of the route that handled them, or `unmatched`,
+/// and methods outside the standard ones are counted as `OTHER` to keep the label sets bounded.
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<RequestMetrics>,
+    open_connections: AtomicUsize,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    thread_pool: Option<Arc<PoolStats>>,
+    file_cache: Option<Arc<CacheStats>>
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Reports queue depth and busy workers of the pool from `ThreadPool::stats`.
+    pub fn thread_pool(mut self, stats: Arc<PoolStats>) -> Self {
+        self.thread_pool = Some(stats);
+        self
+    }
+
+    /// Reports hits, misses and evictions of the cache from `FileCache::stats`.
+    pub fn file_cache(mut self, stats: Arc<CacheStats>) -> Self {
+        self.file_cache = Some(stats);
+        self
+    }
+
+    pub(crate) fn record_request(&self, method: &str, route: Option<&str>, status: u16, elapsed: Duration) {
+        let method = METHODS.iter().find(|known| **known == method).copied().unwrap_or("OTHER");
+        let route = route.unwrap_or("unmatched");
+
+        let mut requests = self.requests.lock().unwrap();
+        *requests.counts.entry((method, route.to_string(), status)).or_insert(0) += 1;
+        requests.latencies.entry(route.to_string()).or_default().observe(elapsed.as_secs_f64());
+    }
+
+    /// Counts a connection as open until the returned guard is dropped.
+    pub(crate) fn open_connection(self: &Arc<Self>) -> OpenConnection {
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+        OpenConnection(Arc::clone(self))
+    }
+
+    pub(crate) fn add_bytes_received(&self, bytes: usize) {
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.open_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        {
+            let requests = self.requests.lock().unwrap();
+
+            write_header(&mut output, "http_requests_total", "counter", "Requests handled, by method, route and status.");
+            for ((method, route, status), count) in &requests.counts {
+                let _ = writeln!(output, "http_requests_total{{method=\"{method}\",route=\"{}\",status=\"{status}\"}} {count}", escape_label(route));
+            }
+
+            write_header(&mut output, "http_request_duration_seconds", "histogram", "Time taken to produce a response, by route.");
+            for (route, histogram) in &requests.latencies {
+                let route = escape_label(route);
+                let mut cumulative = 0;
+                for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                    cumulative += count;
+                    let _ = writeln!(output, "http_request_duration_seconds_bucket{{route=\"{route}\",le=\"{bound}\"}} {cumulative}");
+                }
+                let _ = writeln!(output, "http_request_duration_seconds_bucket{{route=\"{route}\",le=\"+Inf\"}} {}", histogram.count);
+                let _ = writeln!(output, "http_request_duration_seconds_sum{{route=\"{route}\"}} {}", histogram.sum);
+                let _ = writeln!(output, "http_request_duration_seconds_count{{route=\"{route}\"}} {}", histogram.count);
+            }
+        }
+
+        write_header(&mut output, "http_open_connections", "gauge", "Connections currently being served.");
+        let _ = writeln!(output, "http_open_connections {}", self.open_connections());
+        write_header(&mut output, "http_received_bytes_total", "counter", "Bytes read from clients.");
+        let _ = writeln!(output, "http_received_bytes_total {}", self.bytes_received.load(Ordering::Relaxed));
+        write_header(&mut output, "http_sent_bytes_total", "counter", "Bytes written to clients.");
+        let _ = writeln!(output, "http_sent_bytes_total {}", self.bytes_sent.load(Ordering::Relaxed));
+
+        if let Some(stats) = &self.thread_pool {
+            write_header(&mut output, "thread_pool_workers", "gauge", "Worker threads in the pool.");
+            let _ = writeln!(output, "thread_pool_workers {}", stats.size());
+            write_header(&mut output, "thread_pool_busy_workers", "gauge", "Workers currently running a job.");
+            let _ = writeln!(output, "thread_pool_busy_workers {}", stats.busy());
+            write_header(&mut output, "thread_pool_queued_jobs", "gauge", "Jobs waiting for a free worker.");
+            let _ = writeln!(output, "thread_pool_queued_jobs {}", stats.queued());
+        }
+
+        if let Some(stats) = &self.file_cache {
+            write_header(&mut output, "file_cache_hits_total", "counter", "Static files served from the cache.");
+            let _ = writeln!(output, "file_cache_hits_total {}", stats.hits());
+            write_header(&mut output, "file_cache_misses_total", "counter", "Static files read from disk.");
+            let _ = writeln!(output, "file_cache_misses_total {}", stats.misses());
+            write_header(&mut output, "file_cache_evictions_total", "counter", "Files evicted to stay within the cache limits.");
+            let _ = writeln!(output, "file_cache_evictions_total {}", stats.evictions());
+        }
+
+        output
+    }
+
+    /// The response for a `/metrics` endpoint.
+    pub fn get_response(&self) -> HttpResponse {
+        HttpResponse::new(200, "text/plain; version=0.0.4; charset=utf-8", self.render())
+    }
+}
+
+pub(crate) struct OpenConnection(Arc<Metrics>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{sync::Arc, time::Instant};
+use crate::http::{HttpResponse, Request};
+
+/// Wraps request handling: inspect or modify the request, call `next.run` to continue
+/// down the chain (or return early to short-circuit), then inspect or modify the response.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &mut Request, next: Next) -> HttpResponse;
+}
+
+impl<F> Middleware for F where F: Fn(&mut Request, Next) -> HttpResponse + Send + Sync {
+    fn handle(&self, request: &mut Request, next: Next) -> HttpResponse {
+        self(request, next)
+    }
+}
+
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    handler: &'a dyn Fn(&mut Request) -> HttpResponse
+}
+
+impl Next<'_> {
+    pub fn run(self, request: &mut Request) -> HttpResponse {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(request, Next { middleware: rest, handler: self.handler }),
+            None => (self.handler)(request)
+        }
+    }
+}
+
+/// Middleware applied in the order it was added, the first one outermost.
+#[derive(Clone, Default)]
+pub struct MiddlewareChain(Vec<Arc<dyn Middleware>>);
+
+impl MiddlewareChain {
+    pub fn new() -> Self {
+        MiddlewareChain(Vec::new())
+    }
+
+    pub fn push(&mut self, middleware: Arc<dyn Middleware>) {
+        self.0.push(middleware);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn run(&self, request: &mut Request, handler: &dyn Fn(&mut Request) -> HttpResponse) -> HttpResponse {
+        Next { middleware: &self.0, handler }.run(request)
+    }
+}
+
+/// Prints one line per request with its status and duration.
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle(&self, request: &mut Request, next: Next) -> HttpResponse {
+        let start = Instant::now();
+        let (method, target) = (request.method.clone(), request.target().to_string());
+        let remote_addr = request.remote_addr.map_or("-".to_string(), |addr| addr.to_string());
+
+        let response = next.run(request);
+        println!("{remote_addr} \"{method} {target}\" {} {:?}", response.status, start.elapsed());
+
+        response
+    }
+}
+
+/// Adds headers to every response that does not already set them.
+pub struct DefaultHeaders(Vec<(String, String)>);
+
+impl DefaultHeaders {
+    pub fn new(headers: &[(&str, &str)]) -> Self {
+        DefaultHeaders(headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect())
+    }
+}
+
+impl Middleware for DefaultHeaders {
+    fn handle(&self, request: &mut Request, next: Next) -> HttpResponse {
+        let mut response = next.run(request);
+        for (name, value) in &self.0 {
+            if !response.headers.contains(name) {
+                response.headers.insert(name.as_str(), value.as_str());
+            }
+        }
+
+        response
+    }
+}
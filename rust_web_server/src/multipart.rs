@@ -0,0 +1,213 @@
+use std::io::{self, Read};
+use crate::{form::FormError, http::{split_media_type, Headers, Request}};
+
+const CHUNK_SIZE: usize = 8 * 1024;
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+pub const DEFAULT_MAX_PART_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(PartialEq, Eq)]
+enum State {
+    Data,
+    Boundary,
+    Finished
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Streaming `multipart/form-data` reader; parts are read one at a time straight from the body.
+pub struct Multipart<'r> {
+    reader: Box<dyn Read + 'r>,
+    buffer: Vec<u8>,
+    eof: bool,
+    delimiter: Vec<u8>,
+    state: State,
+    max_part_size: u64
+}
+
+impl<'r> Multipart<'r> {
+    pub fn new(request: &'r mut Request) -> Result<Self, FormError> {
+        let (media_type, parameters) = split_media_type(request.header("Content-Type").unwrap_or_default());
+        if media_type != "multipart/form-data" {
+            return Err(FormError::UnsupportedMediaType);
+        }
+
+        let boundary = parameters.into_iter()
+            .find(|(name, _)| name == "boundary")
+            .map(|(_, boundary)| boundary)
+            .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+            .ok_or(FormError::Malformed("missing multipart boundary"))?;
+
+        Ok(Multipart {
+            reader: Box::new(&mut request.body),
+            // The leading CRLF lets the first boundary be found like every later one.
+            buffer: b"\r\n".to_vec(),
+            eof: false,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            state: State::Data,
+            max_part_size: DEFAULT_MAX_PART_SIZE
+        })
+    }
+
+    /// Largest body a single part may have; reading past it fails with `FormError::TooLarge`.
+    pub fn max_part_size(mut self, max_part_size: u64) -> Self {
+        self.max_part_size = max_part_size;
+        self
+    }
+
+    /// Skips whatever is left of the current part and returns the next one.
+    pub fn next_part(&mut self) -> Result<Option<Part<'_, 'r>>, FormError> {
+        let mut scratch = [0u8; CHUNK_SIZE];
+        while self.state == State::Data {
+            self.read_data(&mut scratch)?;
+        }
+
+        if self.state == State::Finished {
+            return Ok(None);
+        }
+
+        self.fill(2)?;
+        if self.buffer.starts_with(b"--") {
+            self.state = State::Finished;
+            return Ok(None);
+        }
+
+        if !self.read_line()?.trim_matches([' ', '\t']).is_empty() {
+            return Err(FormError::Malformed("garbage after multipart boundary"));
+        }
+
+        let mut headers = Headers::new();
+        let mut header_size = 0;
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+
+            header_size += line.len();
+            if header_size > MAX_HEADER_SIZE {
+                return Err(FormError::Malformed("multipart headers too large"));
+            }
+
+            match line.split_once(':') {
+                Some((name, value)) if !name.trim().is_empty() => headers.append(name.trim(), value.trim()),
+                _ => return Err(FormError::Malformed("malformed multipart header"))
+            }
+        }
+
+        self.state = State::Data;
+        let max_part_size = self.max_part_size;
+        Ok(Some(Part { multipart: self, headers, read: 0, max_part_size }))
+    }
+
+    fn fill(&mut self, min: usize) -> io::Result<()> {
+        let mut chunk = [0u8; CHUNK_SIZE];
+        while self.buffer.len() < min && !self.eof {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => self.eof = true,
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error)
+            }
+        }
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<String, FormError> {
+        loop {
+            if let Some(index) = find(&self.buffer, b"\r\n") {
+                let mut line: Vec<u8> = self.buffer.drain(..index + 2).collect();
+                line.truncate(index);
+                return String::from_utf8(line).map_err(|_| FormError::Malformed("multipart header is not UTF-8"));
+            }
+
+            if self.buffer.len() > MAX_HEADER_SIZE {
+                return Err(FormError::Malformed("multipart header line too long"));
+            }
+            if self.eof {
+                return Err(FormError::Malformed("multipart body ended unexpectedly"));
+            }
+            self.fill(self.buffer.len() + 1)?;
+        }
+    }
+
+    // Returns 0 once the delimiter ending the current part has been consumed.
+    fn read_data(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.state != State::Data || buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.fill(self.delimiter.len())?;
+        let available = match find(&self.buffer, &self.delimiter) {
+            Some(0) => {
+                self.buffer.drain(..self.delimiter.len());
+                self.state = State::Boundary;
+                return Ok(0);
+            },
+            Some(index) => index,
+            None if self.eof => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "multipart body ended inside a part")),
+            // Hold back enough bytes to recognise a delimiter split across reads.
+            None => self.buffer.len() + 1 - self.delimiter.len()
+        };
+
+        let length = available.min(buf.len());
+        buf[..length].copy_from_slice(&self.buffer[..length]);
+        self.buffer.drain(..length);
+        Ok(length)
+    }
+}
+
+pub struct Part<'m, 'r> {
+    multipart: &'m mut Multipart<'r>,
+    headers: Headers,
+    read: u64,
+    max_part_size: u64
+}
+
+impl Part<'_, '_> {
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    fn get_disposition_parameter(&self, name: &str) -> Option<String> {
+        let (_, parameters) = split_media_type(self.headers.get("Content-Disposition")?);
+        parameters.into_iter().find(|(key, _)| key == name).map(|(_, value)| value)
+    }
+
+    /// The form field name from `Content-Disposition`.
+    pub fn name(&self) -> Option<String> {
+        self.get_disposition_parameter("name")
+    }
+
+    /// The uploaded file name, if this part is a file.
+    pub fn file_name(&self) -> Option<String> {
+        self.get_disposition_parameter("filename")
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get("Content-Type")
+    }
+
+    pub fn read_to_vec(&mut self) -> Result<Vec<u8>, FormError> {
+        let mut data = Vec::new();
+        self.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    pub fn read_to_string(&mut self) -> Result<String, FormError> {
+        String::from_utf8(self.read_to_vec()?).map_err(|_| FormError::Malformed("part is not UTF-8"))
+    }
+}
+
+impl Read for Part<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.multipart.read_data(buf)?;
+        self.read += read as u64;
+        if self.read > self.max_part_size {
+            return Err(io::Error::new(io::ErrorKind::FileTooLarge, "multipart part too large"));
+        }
+
+        Ok(read)
+    }
+}
@@ -0,0 +1,368 @@
+use std::{io::{self, BufRead, BufReader, Write}, net::{IpAddr, TcpStream, ToSocketAddrs}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex}, thread, time::{Duration, Instant}};
+use crate::{chunked::{ChunkedReader, ChunkedWriter}, http::{has_token, read_response_head, read_status, Body, Headers, HttpResponse, Request}};
+
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "Connection", "Keep-Alive", "Proxy-Connection", "Proxy-Authenticate",
+    "Proxy-Authorization", "TE", "Trailer", "Upgrade"
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Balancing {
+    RoundRobin,
+    LeastConnections
+}
+
+pub struct Upstream {
+    address: String,
+    active_connections: AtomicUsize,
+    failures: AtomicUsize,
+    down_since: Mutex<Option<Instant>>,
+    healthy: AtomicBool
+}
+
+impl Upstream {
+    fn new(address: &str) -> Self {
+        Upstream {
+            address: address.into(),
+            active_connections: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            down_since: Mutex::new(None),
+            healthy: AtomicBool::new(true)
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.active_connections.load(Ordering::SeqCst)
+    }
+
+    /// False while the active health check fails or after too many failed requests.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst) && self.down_since.lock().unwrap().is_none()
+    }
+
+    fn is_available(&self, fail_timeout: Duration) -> bool {
+        let passively_up = match *self.down_since.lock().unwrap() {
+            Some(down_since) => down_since.elapsed() >= fail_timeout,
+            None => true
+        };
+
+        passively_up && self.healthy.load(Ordering::SeqCst)
+    }
+
+    fn mark_success(&self) {
+        self.failures.store(0, Ordering::SeqCst);
+        *self.down_since.lock().unwrap() = None;
+    }
+
+    fn mark_failure(&self, max_failures: usize) {
+        let failures = self.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if failures >= max_failures {
+            println!("Upstream {} marked down after {failures} failures.", self.address);
+            *self.down_since.lock().unwrap() = Some(Instant::now());
+        }
+    }
+}
+
+// Counts a request against its upstream until the response body has been relayed.
+struct ConnectionGuard(Arc<Upstream>);
+
+impl ConnectionGuard {
+    fn new(upstream: Arc<Upstream>) -> Self {
+        upstream.active_connections.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard(upstream)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct UpstreamPool {
+    upstreams: Vec<Arc<Upstream>>,
+    balancing: Balancing,
+    next: AtomicUsize,
+    connect_timeout: Duration,
+    response_timeout: Duration,
+    max_failures: usize,
+    fail_timeout: Duration,
+    preserve_host: bool
+}
+
+impl UpstreamPool {
+    pub fn new(addresses: &[&str], balancing: Balancing) -> Self {
+        UpstreamPool {
+            upstreams: addresses.iter().map(|address| Arc::new(Upstream::new(address))).collect(),
+            balancing,
+            next: AtomicUsize::new(0),
+            connect_timeout: Duration::from_secs(5),
+            response_timeout: Duration::from_secs(30),
+            max_failures: 3,
+            fail_timeout: Duration::from_secs(10),
+            preserve_host: false
+        }
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Longest wait for the upstream to answer before responding with 504.
+    pub fn response_timeout(mut self, response_timeout: Duration) -> Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    /// Takes an upstream out of rotation for `fail_timeout` after `max_failures` failed requests in a row.
+    pub fn max_failures(mut self, max_failures: usize, fail_timeout: Duration) -> Self {
+        self.max_failures = max_failures.max(1);
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    /// Sends the client's `Host` header upstream instead of the upstream address.
+    pub fn preserve_host(mut self, preserve_host: bool) -> Self {
+        self.preserve_host = preserve_host;
+        self
+    }
+
+    pub fn upstreams(&self) -> &[Arc<Upstream>] {
+        &self.upstreams
+    }
+
+    /// Probes `path` on every upstream each `interval` until the pool is dropped.
+    pub fn start_health_checks(self: &Arc<Self>, path: &str, interval: Duration) {
+        let pool = Arc::downgrade(self);
+        let path = path.to_string();
+
+        thread::spawn(move || {
+            while let Some(pool) = pool.upgrade() {
+                for upstream in &pool.upstreams {
+                    let healthy = check_health(upstream, &path, pool.connect_timeout, pool.response_timeout);
+                    if healthy != upstream.healthy.swap(healthy, Ordering::SeqCst) {
+                        println!("Upstream {} is now {}.", upstream.address, if healthy { "healthy" } else { "unhealthy" });
+                    }
+                    if healthy {
+                        upstream.mark_success();
+                    }
+                }
+
+                drop(pool);
+                thread::sleep(interval);
+            }
+        });
+    }
+
+    // Available upstreams in the order they should be tried.
+    fn get_candidates(&self) -> Vec<Arc<Upstream>> {
+        let mut candidates: Vec<Arc<Upstream>> = self.upstreams.iter()
+            .filter(|upstream| upstream.is_available(self.fail_timeout))
+            .cloned()
+            .collect();
+        if candidates.is_empty() {
+            return candidates;
+        }
+
+        let start = self.next.fetch_add(1, Ordering::SeqCst) % candidates.len();
+        candidates.rotate_left(start);
+
+        if self.balancing == Balancing::LeastConnections {
+            candidates.sort_by_key(|upstream| upstream.active_connections());
+        }
+
+        candidates
+    }
+}
+
+pub(crate) fn connect(address: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("could not resolve {address}"));
+
+    for socket_addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = error
+        }
+    }
+
+    Err(last_error)
+}
+
+fn check_health(upstream: &Upstream, path: &str, connect_timeout: Duration, response_timeout: Duration) -> bool {
+    let check = || -> io::Result<u16> {
+        let mut stream = connect(&upstream.address, connect_timeout)?;
+        stream.set_read_timeout(Some(response_timeout))?;
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", upstream.address)?;
+
+        read_status(&mut BufReader::new(stream))
+    };
+
+    matches!(check(), Ok(200..=399))
+}
+
+fn remove_hop_by_hop_headers(headers: &mut Headers) {
+    let listed: Vec<String> = headers.get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_string())
+        .collect();
+
+    for name in listed.iter().map(String::as_str).chain(HOP_BY_HOP_HEADERS) {
+        headers.remove(name);
+    }
+}
+
+fn format_forwarded_for(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{ip}]\"")
+    }
+}
+
+// A quoted-string for a `Forwarded` parameter, with quotes and backslashes escaped.
+fn quote_forwarded_value(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn get_upstream_headers(pool: &UpstreamPool, request: &Request, upstream: &Upstream) -> Headers {
+    let mut headers = request.headers.clone();
+    remove_hop_by_hop_headers(&mut headers);
+
+    let proto = if request.secure { "https" } else { "http" };
+    let original_host = request.host().map(String::from);
+
+    let mut forwarded = Vec::new();
+    if let Some(ip) = request.remote_addr.map(|addr| addr.ip()) {
+        let forwarded_for = match headers.get("X-Forwarded-For") {
+            Some(existing) => format!("{existing}, {ip}"),
+            None => ip.to_string()
+        };
+        headers.insert("X-Forwarded-For", forwarded_for);
+        forwarded.push(format!("for={}", format_forwarded_for(ip)));
+    }
+
+    if let Some(host) = &original_host {
+        forwarded.push(format!("host={}", quote_forwarded_value(host)));
+        headers.insert("X-Forwarded-Host", host.as_str());
+    }
+    forwarded.push(format!("proto={proto}"));
+    headers.insert("X-Forwarded-Proto", proto);
+
+    let forwarded = match headers.get("Forwarded") {
+        Some(existing) => format!("{existing}, {}", forwarded.join(";")),
+        None => forwarded.join(";")
+    };
+    headers.insert("Forwarded", forwarded);
+
+    if !pool.preserve_host || original_host.is_none() {
+        headers.insert("Host", upstream.address.as_str());
+    }
+    headers.insert("Connection", "close");
+
+    headers
+}
+
+fn send_request(pool: &UpstreamPool, request: &mut Request, upstream: &Upstream, stream: &mut TcpStream) -> io::Result<()> {
+    let mut head = format!("{} {} HTTP/1.1\r\n", request.method, request.target());
+    for (name, value) in get_upstream_headers(pool, request, upstream).iter() {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    // A chunked body arrives decoded, so it is chunked again under the `Transfer-Encoding` passed along.
+    if request.headers.contains("Transfer-Encoding") {
+        let mut chunked = ChunkedWriter::new(&mut *stream);
+        io::copy(&mut request.body, &mut chunked)?;
+        chunked.finish()?;
+    } else {
+        io::copy(&mut request.body, stream)?;
+    }
+    stream.flush()
+}
+
+pub(crate) fn get_gateway_error_response(error: &io::Error) -> HttpResponse {
+    match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => HttpResponse::error(504),
+        _ => HttpResponse::error(502)
+    }
+}
+
+// Interim responses are not relayed, `101` included, since upgrades are not proxied.
+fn read_final_response_head<R: BufRead>(reader: &mut R) -> io::Result<(u16, Headers)> {
+    loop {
+        let (status, headers) = read_response_head(reader)?;
+        if !(100..200).contains(&status) {
+            return Ok((status, headers));
+        }
+    }
+}
+
+/// Sends `request` to the next available upstream of `pool` and relays its response.
+pub fn forward(pool: &UpstreamPool, request: &mut Request) -> HttpResponse {
+    let mut connection = None;
+    for upstream in pool.get_candidates() {
+        match connect(&upstream.address, pool.connect_timeout) {
+            Ok(stream) => {
+                connection = Some((upstream, stream));
+                break;
+            },
+            Err(error) => {
+                println!("Failed to connect to upstream {}: {error}", upstream.address);
+                upstream.mark_failure(pool.max_failures);
+            }
+        }
+    }
+
+    let Some((upstream, mut stream)) = connection else {
+        return HttpResponse::error(502);
+    };
+    let guard = ConnectionGuard::new(Arc::clone(&upstream));
+
+    let response_head = send_request(pool, request, &upstream, &mut stream)
+        .and_then(|_| stream.set_read_timeout(Some(pool.response_timeout)))
+        .map(|_| BufReader::new(stream))
+        .and_then(|mut reader| read_final_response_head(&mut reader).map(|head| (head, reader)));
+
+    let ((status, mut headers), mut reader) = match response_head {
+        Ok(response_head) => response_head,
+        Err(error) => {
+            println!("Upstream {} failed: {error}", upstream.address);
+            upstream.mark_failure(pool.max_failures);
+            return get_gateway_error_response(&error);
+        }
+    };
+    upstream.mark_success();
+
+    // The upstream closes after its response, so its body ends at EOF unless it is chunked. A chunked
+    // body is decoded here and framed again by the server to suit the client's version.
+    remove_hop_by_hop_headers(&mut headers);
+    let chunked = has_token(headers.get("Transfer-Encoding"), "chunked");
+    if chunked {
+        headers.remove("Transfer-Encoding");
+        headers.remove("Content-Length");
+    }
+
+    let body = Body::Stream(Box::new(move |writer| {
+        let _guard = guard;
+        match chunked {
+            true => io::copy(&mut ChunkedReader::new(reader), writer)?,
+            false => io::copy(&mut reader, writer)?
+        };
+        Ok(())
+    }));
+
+    HttpResponse { status, headers, body, upgrade: None, default_body: false }
+}
@@ -0,0 +1,208 @@
+use std::{collections::HashMap, net::IpAddr, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc, Mutex}, time::{Duration, Instant}};
+use crate::{http::{HttpResponse, Request}, middleware::{Middleware, Next}};
+
+const MAX_TRACKED_KEYS: usize = 100_000;
+// Once the limit is reached, refilled buckets are swept out at most this often.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// Buckets forgotten at once when every tracked key is still using its burst.
+const EVICTION_BATCH: usize = MAX_TRACKED_KEYS / 100;
+
+#[derive(Default)]
+pub struct ThrottleStats {
+    allowed: AtomicU64,
+    throttled: AtomicU64
+}
+
+impl ThrottleStats {
+    pub fn allowed(&self) -> u64 {
+        self.allowed.load(Ordering::Relaxed)
+    }
+
+    pub fn throttled(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+}
+
+/// What requests are counted against: the client address, or a header such as an API key
+/// (falling back to the address when the header is missing).
+#[derive(Clone, Debug)]
+pub enum RateLimitKey {
+    ClientIp,
+    Header(String)
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant
+}
+
+struct Buckets {
+    buckets: HashMap<String, TokenBucket>,
+    next_sweep: Instant
+}
+
+/// Token bucket per key: `burst` requests at once, refilled at `rate` requests per second.
+/// Requests over the limit get 429 with `Retry-After`.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    key: RateLimitKey,
+    buckets: Mutex<Buckets>,
+    stats: Arc<ThrottleStats>
+}
+
+impl RateLimiter {
+    /// Panics if `rate` is not positive.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        assert!(rate > 0.0, "rate must be positive");
+        RateLimiter {
+            rate,
+            burst: f64::from(burst.max(1)),
+            key: RateLimitKey::ClientIp,
+            buckets: Mutex::new(Buckets { buckets: HashMap::new(), next_sweep: Instant::now() }),
+            stats: Arc::new(ThrottleStats::default())
+        }
+    }
+
+    pub fn key(mut self, key: RateLimitKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Shared with the limiter, so it can be read after the limiter is handed to a server or route.
+    pub fn stats(&self) -> Arc<ThrottleStats> {
+        Arc::clone(&self.stats)
+    }
+
+    fn get_key(&self, request: &Request) -> String {
+        let header = match &self.key {
+            RateLimitKey::Header(name) => request.header(name),
+            RateLimitKey::ClientIp => None
+        };
+
+        match header {
+            Some(value) => format!("header:{value}"),
+            None => request.remote_addr.map_or("-".to_string(), |addr| addr.ip().to_canonical().to_string())
+        }
+    }
+
+    // Keeps the number of tracked keys under the limit without scanning them all on every request.
+    fn make_room(&self, buckets: &mut Buckets, now: Instant) {
+        if now >= buckets.next_sweep {
+            buckets.next_sweep = now + SWEEP_INTERVAL;
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.buckets.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst);
+        }
+
+        if buckets.buckets.len() >= MAX_TRACKED_KEYS {
+            let evicted: Vec<String> = buckets.buckets.keys().take(EVICTION_BATCH).cloned().collect();
+            for key in evicted {
+                buckets.buckets.remove(&key);
+            }
+        }
+    }
+
+    /// Takes a token for `key`, or returns how many seconds until one is available.
+    pub fn check(&self, key: &str) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.buckets.len() >= MAX_TRACKED_KEYS && !buckets.buckets.contains_key(key) {
+            self.make_room(&mut buckets, now);
+        }
+
+        let bucket = buckets.buckets.entry(key.to_string()).or_insert(TokenBucket { tokens: self.burst, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            self.stats.allowed.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        self.stats.throttled.fetch_add(1, Ordering::Relaxed);
+        Err((((1.0 - bucket.tokens) / self.rate).ceil() as u64).max(1))
+    }
+}
+
+pub fn get_too_many_requests_response(retry_after: u64) -> HttpResponse {
+    HttpResponse::error(429).with_header("Retry-After", &retry_after.to_string())
+}
+
+impl Middleware for RateLimiter {
+    fn handle(&self, request: &mut Request, next: Next) -> HttpResponse {
+        match self.check(&self.get_key(request)) {
+            Ok(()) => next.run(request),
+            Err(retry_after) => get_too_many_requests_response(retry_after)
+        }
+    }
+}
+
+/// Caps open connections in total and per client address.
+pub struct ConnectionLimits {
+    max_connections: usize,
+    max_per_ip: usize,
+    active: AtomicUsize,
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+    rejected: AtomicU64
+}
+
+impl ConnectionLimits {
+    pub fn new(max_connections: usize, max_per_ip: usize) -> Self {
+        ConnectionLimits {
+            max_connections,
+            max_per_ip,
+            active: AtomicUsize::new(0),
+            per_ip: Mutex::new(HashMap::new()),
+            rejected: AtomicU64::new(0)
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Reserves a slot for a connection from `ip`, released when the permit is dropped.
+    pub fn acquire(self: &Arc<Self>, ip: Option<IpAddr>) -> Option<ConnectionPermit> {
+        let ip = ip.map(|ip| ip.to_canonical());
+        let mut per_ip = self.per_ip.lock().unwrap();
+
+        let ip_count = ip.and_then(|ip| per_ip.get(&ip).copied()).unwrap_or(0);
+        if self.active.load(Ordering::Relaxed) >= self.max_connections || ip_count >= self.max_per_ip {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        self.active.fetch_add(1, Ordering::Relaxed);
+        if let Some(ip) = ip {
+            *per_ip.entry(ip).or_insert(0) += 1;
+        }
+        Some(ConnectionPermit { limits: Arc::clone(self), ip })
+    }
+}
+
+pub struct ConnectionPermit {
+    limits: Arc<ConnectionLimits>,
+    ip: Option<IpAddr>
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut per_ip = self.limits.per_ip.lock().unwrap();
+        if let Some(ip) = self.ip {
+            if let Some(count) = per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+        self.limits.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
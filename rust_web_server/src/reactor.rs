@@ -0,0 +1,297 @@
+use std::{collections::HashMap, io::{self, Read}, net::{TcpListener, TcpStream}, os::fd::AsRawFd, sync::{mpsc::{self, Receiver, Sender}, Arc}, time::{Duration, Instant}};
+use mio::{unix::SourceFd, Events, Interest, Poll, Token, Waker};
+use crate::{http::{read_request, HttpResponse, MAX_HEAD_SIZE}, metrics::OpenConnection, ratelimit::{get_too_many_requests_response, ConnectionPermit}, server::Server};
+
+const LISTENER: Token = Token(0);
+const WAKER: Token = Token(1);
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    deadline: Instant,
+    permit: Option<ConnectionPermit>,
+    served: usize,
+    // Counts the connection as open while it waits here; the worker it goes to counts it from then on.
+    open_connection: Option<OpenConnection>
+}
+
+// Where workers send kept-alive connections back to wait for their next request.
+struct Returns {
+    sender: Sender<Connection>,
+    receiver: Receiver<Connection>,
+    waker: Arc<Waker>
+}
+
+enum Progress {
+    Incomplete,
+    Ready,
+    HeadTooLarge
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// Best effort: the socket is non-blocking, and the connection is closed right after either way.
+fn close_with(mut stream: TcpStream, response: HttpResponse) {
+    let _ = stream.set_nonblocking(true);
+    let _ = response.with_header("Connection", "close").write_to(&mut stream);
+}
+
+fn hand_over(server: &Server, returns: &Returns, connection: Connection, progress: Progress) {
+    match progress {
+        Progress::Ready if connection.stream.set_nonblocking(false).is_ok() => {
+            let (sender, waker) = (returns.sender.clone(), Arc::clone(&returns.waker));
+            let park = Box::new(move |stream, buffer, permit, served| {
+                let connection = Connection { stream, buffer, deadline: Instant::now(), permit, served, open_connection: None };
+                if sender.send(connection).is_ok() {
+                    let _ = waker.wake();
+                }
+            });
+            server.handle_buffered_connection(connection.stream, connection.buffer, connection.permit, connection.served, Some(park));
+        },
+        Progress::HeadTooLarge => close_with(connection.stream, HttpResponse::error(431)),
+        _ => {}
+    }
+}
+
+/// Accepts and reads connections on one thread with epoll, handing each to the server's
+/// thread pool only once its request has arrived, so slow or idle clients do not tie up workers.
+/// Kept-alive connections come back here between requests. Bodies larger than
+/// `max_buffered_body` are streamed by the worker instead.
+///
+/// After `Server::stop_accepting`, it stops listening and closes kept-alive connections waiting for
+/// another request, then returns once the requests it was still receiving have gone to workers.
+pub struct Reactor {
+    max_connections: usize,
+    header_timeout: Duration,
+    max_head_size: usize,
+    max_buffered_body: u64
+}
+
+impl Default for Reactor {
+    fn default() -> Self {
+        Reactor::new()
+    }
+}
+
+impl Reactor {
+    pub fn new() -> Self {
+        Reactor {
+            max_connections: 10_000,
+            header_timeout: Duration::from_secs(10),
+            max_head_size: MAX_HEAD_SIZE,
+            max_buffered_body: 1024 * 1024
+        }
+    }
+
+    /// Connections waiting for their request beyond this many are turned away with 503.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// How long a client may take to send its request before getting 408.
+    pub fn header_timeout(mut self, header_timeout: Duration) -> Self {
+        self.header_timeout = header_timeout;
+        self
+    }
+
+    pub fn max_head_size(mut self, max_head_size: usize) -> Self {
+        self.max_head_size = max_head_size;
+        self
+    }
+
+    pub fn max_buffered_body(mut self, max_buffered_body: u64) -> Self {
+        self.max_buffered_body = max_buffered_body;
+        self
+    }
+
+    /// Serves plaintext connections from `listener` until polling fails or the server stops accepting.
+    pub fn run(&self, server: &Server, listener: TcpListener) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        let mut poll = Poll::new()?;
+        poll.registry().register(&mut SourceFd(&listener.as_raw_fd()), LISTENER, Interest::READABLE)?;
+        let (sender, receiver) = mpsc::channel();
+        let returns = Returns { sender, receiver, waker: Arc::new(Waker::new(poll.registry(), WAKER)?) };
+
+        let mut events = Events::with_capacity(1024);
+        let mut connections: HashMap<Token, Connection> = HashMap::new();
+        let mut next_token = WAKER.0 + 1;
+        let mut listener = Some(listener);
+
+        loop {
+            if let Err(error) = poll.poll(&mut events, Some(Duration::from_secs(1))) {
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error);
+            }
+
+            for event in events.iter() {
+                if event.token() == LISTENER {
+                    if let Some(listener) = &listener {
+                        self.accept(server, listener, &poll, &mut connections, &mut next_token);
+                    }
+                    continue;
+                }
+                if event.token() == WAKER {
+                    let idle_timeout = server.keep_alive().unwrap_or(self.header_timeout);
+                    for mut connection in returns.receiver.try_iter() {
+                        connection.deadline = Instant::now() + idle_timeout;
+                        self.resume(server, &poll, &returns, &mut connections, &mut next_token, connection);
+                    }
+                    continue;
+                }
+
+                let Some(connection) = connections.get_mut(&event.token()) else {
+                    continue;
+                };
+                let progress = self.read(connection);
+                if let Ok(Progress::Incomplete) = progress {
+                    continue;
+                }
+
+                let connection = connections.remove(&event.token()).unwrap();
+                let _ = poll.registry().deregister(&mut SourceFd(&connection.stream.as_raw_fd()));
+                if let Ok(progress) = progress {
+                    hand_over(server, &returns, connection, progress);
+                }
+            }
+
+            let now = Instant::now();
+            let expired: Vec<Token> = connections.iter()
+                .filter(|(_, connection)| now >= connection.deadline)
+                .map(|(token, _)| *token)
+                .collect();
+            for token in expired {
+                let connection = connections.remove(&token).unwrap();
+                let _ = poll.registry().deregister(&mut SourceFd(&connection.stream.as_raw_fd()));
+                if !connection.buffer.is_empty() {
+                    close_with(connection.stream, HttpResponse::error(408));
+                }
+            }
+
+            if !server.is_accepting() {
+                if let Some(listener) = listener.take() {
+                    let _ = poll.registry().deregister(&mut SourceFd(&listener.as_raw_fd()));
+                }
+                connections.retain(|_, connection| {
+                    let waiting = connection.served > 0 && connection.buffer.is_empty();
+                    if waiting {
+                        let _ = poll.registry().deregister(&mut SourceFd(&connection.stream.as_raw_fd()));
+                    }
+                    !waiting
+                });
+                if connections.is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn accept(&self, server: &Server, listener: &TcpListener, poll: &Poll, connections: &mut HashMap<Token, Connection>, next_token: &mut usize) {
+        loop {
+            let (stream, remote_addr) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    println!("Failed to accept connection: {error}");
+                    return;
+                }
+            };
+
+            if connections.len() >= self.max_connections {
+                close_with(stream, HttpResponse::error(503));
+                continue;
+            }
+
+            let (stream, permit) = match server.acquire_permit(stream, Some(remote_addr)) {
+                Ok(accepted) => accepted,
+                Err(stream) => {
+                    close_with(stream, get_too_many_requests_response(1));
+                    continue;
+                }
+            };
+
+            let token = Token(*next_token);
+            *next_token += 1;
+            if stream.set_nonblocking(true).is_err() {
+                continue;
+            }
+            if poll.registry().register(&mut SourceFd(&stream.as_raw_fd()), token, Interest::READABLE).is_err() {
+                continue;
+            }
+
+            let open_connection = server.metrics().map(|metrics| metrics.open_connection());
+            connections.insert(token, Connection { stream, buffer: Vec::new(), deadline: Instant::now() + self.header_timeout, permit, served: 0, open_connection });
+        }
+    }
+
+    // A returned connection may already hold its next request, pipelined behind the last one.
+    fn resume(&self, server: &Server, poll: &Poll, returns: &Returns, connections: &mut HashMap<Token, Connection>, next_token: &mut usize, mut connection: Connection) {
+        match self.get_progress(&connection.buffer) {
+            Progress::Incomplete if connection.buffer.is_empty() && !server.is_accepting() => return,
+            Progress::Incomplete => {},
+            progress => return hand_over(server, returns, connection, progress)
+        }
+
+        let token = Token(*next_token);
+        *next_token += 1;
+        if connection.stream.set_nonblocking(true).is_err() {
+            return;
+        }
+        // Registering a socket that is already readable reports it straight away, so nothing sent meanwhile is missed.
+        if poll.registry().register(&mut SourceFd(&connection.stream.as_raw_fd()), token, Interest::READABLE).is_err() {
+            return;
+        }
+        connection.open_connection = server.metrics().map(|metrics| metrics.open_connection());
+        connections.insert(token, connection);
+    }
+
+    // Readiness is edge-triggered, so keep reading until the socket would block or the request is complete.
+    fn read(&self, connection: &mut Connection) -> io::Result<Progress> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            match connection.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => {
+                    connection.buffer.extend_from_slice(&chunk[..read]);
+                    match self.get_progress(&connection.buffer) {
+                        Progress::Incomplete => continue,
+                        progress => return Ok(progress)
+                    }
+                },
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(Progress::Incomplete),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error)
+            }
+        }
+    }
+
+    fn get_progress(&self, buffer: &[u8]) -> Progress {
+        let Some(head_end) = find(buffer, b"\r\n\r\n").map(|index| index + 4) else {
+            return match buffer.len() > self.max_head_size {
+                true => Progress::HeadTooLarge,
+                false => Progress::Incomplete
+            };
+        };
+
+        // Anything the worker has to reject, or stream itself, can go to it straight away.
+        let Ok(request) = read_request(&mut &buffer[..head_end]) else {
+            return Progress::Ready;
+        };
+        // A client expecting `100 Continue` sends no body until the worker answers.
+        if request.headers.contains("Transfer-Encoding") || request.headers.contains("Expect") {
+            return Progress::Ready;
+        }
+
+        let content_length = request.header("Content-Length").and_then(|length| length.parse::<u64>().ok()).unwrap_or(0);
+        match content_length > self.max_buffered_body || (buffer.len() - head_end) as u64 >= content_length {
+            true => Progress::Ready,
+            false => Progress::Incomplete
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::{io, sync::Arc};
+use crate::{cgi::{self, Cgi}, cors::Cors, fastcgi::{self, FastCgi}, http::{HttpResponse, Request}, middleware::{Middleware, MiddlewareChain}, proxy::{self, UpstreamPool}, server::Handler, sse::{self, EventStream}, websocket::{self, WebSocket}};
+
+struct Route {
+    method: String,
+    path: String,
+    handler: Arc<Handler>,
+    middleware: MiddlewareChain
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> bool {
+        match self.path.strip_suffix("/*") {
+            Some(prefix) => path == prefix || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/')),
+            None => self.path == path
+        }
+    }
+}
+
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Arc<Handler>
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router { routes: Vec::new(), fallback: Arc::new(|_: &mut Request| HttpResponse::error(404)) }
+    }
+
+    /// `path` matches exactly, or as a prefix when it ends in `/*`; `method` `*` matches any method.
+    pub fn route<F>(mut self, method: &str, path: &str, handler: F) -> Self where F: Fn(&mut Request) -> HttpResponse + Send + Sync + 'static {
+        self.routes.push(Route {
+            method: method.to_ascii_uppercase(),
+            path: path.into(),
+            handler: Arc::new(handler),
+            middleware: MiddlewareChain::new()
+        });
+        self
+    }
+
+    /// Wraps the most recently registered route in `middleware`.
+    ///
+    /// Panics if no route has been registered yet.
+    pub fn middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        let route = self.routes.last_mut().expect("middleware must follow a route");
+        route.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Applies `cors` to the routes registered so far on the most recent route's path and answers
+    /// OPTIONS requests, including preflights, there. Add the routes' other middleware before this.
+    ///
+    /// Panics if no route has been registered yet.
+    pub fn cors(mut self, cors: Cors) -> Self {
+        let cors = Arc::new(cors);
+        let path = self.routes.last().expect("cors must follow a route").path.clone();
+
+        let mut allowed_methods = Vec::new();
+        for route in self.routes.iter_mut().filter(|route| route.path == path) {
+            route.middleware.push(cors.clone());
+            allowed_methods.push(route.method.clone());
+        }
+        allowed_methods.push("OPTIONS".to_string());
+        let allow = allowed_methods.join(", ");
+
+        let mut options = MiddlewareChain::new();
+        options.push(cors);
+        self.routes.push(Route {
+            method: "OPTIONS".to_string(),
+            path,
+            handler: Arc::new(move |_: &mut Request| HttpResponse::no_content().with_header("Allow", &allow)),
+            middleware: options
+        });
+        self
+    }
+
+    pub fn get<F>(self, path: &str, handler: F) -> Self where F: Fn(&mut Request) -> HttpResponse + Send + Sync + 'static {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post<F>(self, path: &str, handler: F) -> Self where F: Fn(&mut Request) -> HttpResponse + Send + Sync + 'static {
+        self.route("POST", path, handler)
+    }
+
+    pub fn any<F>(self, path: &str, handler: F) -> Self where F: Fn(&mut Request) -> HttpResponse + Send + Sync + 'static {
+        self.route("*", path, handler)
+    }
+
+    /// Forwards every request under `path` to `upstreams`.
+    pub fn proxy(self, path: &str, upstreams: Arc<UpstreamPool>) -> Self {
+        self.any(path, move |request| proxy::forward(&upstreams, request))
+    }
+
+    /// Runs `cgi` for every request under `path`; with a `/*` pattern the rest of the path becomes `PATH_INFO`.
+    pub fn cgi(self, path: &str, cgi: Arc<Cgi>) -> Self {
+        self.any(path, move |request| cgi::run(&cgi, request))
+    }
+
+    /// Passes every request under `path` to the FastCGI responder of `fastcgi`.
+    pub fn fastcgi(self, path: &str, fastcgi: Arc<FastCgi>) -> Self {
+        self.any(path, move |request| fastcgi::forward(&fastcgi, request))
+    }
+
+    /// Registers a WebSocket endpoint; `handler` runs on the worker thread once the handshake succeeds.
+    pub fn websocket<F>(self, path: &str, handler: F) -> Self where F: Fn(&mut WebSocket) + Send + Sync + 'static {
+        let handler = Arc::new(handler);
+        self.get(path, move |request| websocket::upgrade(request, Arc::clone(&handler)))
+    }
+
+    /// Registers a Server-Sent Events endpoint; `handler` keeps writing events until it returns.
+    pub fn event_stream<F>(self, path: &str, handler: F) -> Self where F: Fn(&mut EventStream) -> io::Result<()> + Send + Sync + 'static {
+        let handler = Arc::new(handler);
+        self.get(path, move |request| {
+            let handler = Arc::clone(&handler);
+            sse::event_stream(request, move |events| handler(events))
+        })
+    }
+
+    /// Handler for requests that match no route.
+    pub fn fallback<F>(mut self, handler: F) -> Self where F: Fn(&mut Request) -> HttpResponse + Send + Sync + 'static {
+        self.fallback = Arc::new(handler);
+        self
+    }
+
+    pub fn handle(&self, request: &mut Request) -> HttpResponse {
+        match self.try_handle(request) {
+            Some(response) => response,
+            None => self.handle_fallback(request)
+        }
+    }
+
+    /// Like `handle`, but returns `None` instead of calling the fallback when no route matches the path.
+    pub fn try_handle(&self, request: &mut Request) -> Option<HttpResponse> {
+        let routes: Vec<&Route> = self.routes.iter().filter(|route| route.matches(request.path())).collect();
+        if routes.is_empty() {
+            return None;
+        }
+
+        // HEAD is answered by the GET route, without the body, unless it has a route of its own.
+        let route = routes.iter().find(|route| route.method == "*" || route.method == request.method)
+            .or_else(|| routes.iter().find(|route| request.method == "HEAD" && route.method == "GET"));
+        if let Some(route) = route {
+            request.route = Some(route.path.clone());
+            let response = route.middleware.run(request, route.handler.as_ref());
+            return Some(match request.method.as_str() {
+                "HEAD" => response.without_body(),
+                _ => response
+            });
+        }
+
+        let mut allowed_methods: Vec<&str> = Vec::new();
+        for method in routes.iter().flat_map(|route| match route.method.as_str() {
+            "GET" => vec!["GET", "HEAD"],
+            method => vec![method]
+        }) {
+            if !allowed_methods.contains(&method) {
+                allowed_methods.push(method);
+            }
+        }
+
+        Some(HttpResponse::error(405).with_header("Allow", &allowed_methods.join(", ")))
+    }
+
+    pub fn handle_fallback(&self, request: &mut Request) -> HttpResponse {
+        (self.fallback)(request)
+    }
+}
@@ -0,0 +1,89 @@
+use std::{fs::File, io::{self, Write}, net::TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(target_os = "linux")]
+use std::{os::fd::AsRawFd, ptr};
+use rustls::{ServerConnection, StreamOwned};
+use crate::http::{copy_file, Body, HttpResponse};
+
+/// A connection that can send file contents itself, such as straight from the file to a socket.
+pub trait SendFile: Write {
+    /// Writes the next `length` bytes of `file`. By default they are copied through a buffer.
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<()> {
+        copy_file(file, length, self)
+    }
+}
+
+impl<S: SendFile + ?Sized> SendFile for &mut S {
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<()> {
+        (**self).send_file(file, length)
+    }
+}
+
+// Encrypted data has to pass through user space anyway.
+impl<S: io::Read + Write> SendFile for StreamOwned<ServerConnection, S> {}
+
+// Sends from the file's own position, advancing it, straight to the socket.
+#[cfg(target_os = "linux")]
+fn send_file_to_socket<S: AsRawFd + Write>(socket: &mut S, file: &mut File, length: u64) -> io::Result<()> {
+    // The most Linux sends in one call.
+    const MAX_CHUNK: u64 = 0x7fff_f000;
+
+    let mut remaining = length;
+    while remaining > 0 {
+        let sent = unsafe { libc::sendfile(socket.as_raw_fd(), file.as_raw_fd(), ptr::null_mut(), remaining.min(MAX_CHUNK) as usize) };
+        match sent {
+            -1 => {
+                let error = io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // Not supported for this file, such as on some file systems.
+                    Some(libc::EINVAL | libc::ENOSYS) if remaining == length => return copy_file(file, length, socket),
+                    _ => return Err(error)
+                }
+            },
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shorter than its Content-Length")),
+            sent => remaining -= sent as u64
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+impl SendFile for TcpStream {
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<()> {
+        send_file_to_socket(self, file, length)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl SendFile for UnixStream {
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<()> {
+        send_file_to_socket(self, file, length)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl SendFile for TcpStream {}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+impl SendFile for UnixStream {}
+
+impl HttpResponse {
+    /// Like `write_to`, but hands a file body to the connection to send itself.
+    pub fn send_to<S: SendFile>(self, stream: &mut S) -> io::Result<()> {
+        if !matches!(self.body, Body::File(..)) {
+            return self.write_to(stream);
+        }
+
+        // Headers have to reach the socket before the file does.
+        self.write_head(stream)?;
+        stream.flush()?;
+
+        if let Body::File(mut file, length) = self.body {
+            stream.send_file(&mut file, length)?;
+        }
+        stream.flush()
+    }
+}
@@ -0,0 +1,653 @@
+use std::{cell::Cell, fs::File, io::{self, BufRead, BufReader, Read, Write}, net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs}, rc::Rc, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread, time::{Duration, Instant}};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use rustls::{ServerConnection, StreamOwned};
+use crate::{chunked::ChunkedReader, health::Health, http::{has_token, read_request, strip_port, Body, HttpResponse, ReadTimeout, Request, RequestError}, listener::{wake_tcp_listener, Connection, Listener}, metrics::Metrics, middleware::{Middleware, MiddlewareChain}, ratelimit::{get_too_many_requests_response, ConnectionLimits, ConnectionPermit}, sendfile::SendFile, ThreadPool};
+
+pub type Handler = dyn Fn(&mut Request) -> HttpResponse + Send + Sync;
+
+/// Takes back a kept-alive connection after a response, with the bytes already read from it and the
+/// number of requests served on it, so it can wait for the next request without holding a worker.
+pub(crate) type Park<S> = Box<dyn FnOnce(S, Vec<u8>, Option<ConnectionPermit>, usize) + Send>;
+
+pub struct Server {
+    thread_pool: ThreadPool,
+    handler: Arc<Handler>,
+    middleware: MiddlewareChain,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    https_redirect_port: Option<u16>,
+    connection_limits: Option<Arc<ConnectionLimits>>,
+    metrics: Option<Arc<Metrics>>,
+    health: Arc<Health>,
+    keep_alive: Option<Duration>,
+    accepting: AtomicBool
+}
+
+// Requests served on one connection before it is closed.
+const MAX_REQUESTS_PER_CONNECTION: usize = 100;
+// How long a worker waits for the first request on a connection it has been given.
+const FIRST_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Unread request body the server will discard to reuse a connection; with more left it is closed instead.
+const MAX_DRAINED_BODY: u64 = 64 * 1024;
+
+impl Server {
+    pub fn new<F>(thread_pool: ThreadPool, handler: F) -> Self where F: Fn(&mut Request) -> HttpResponse + Send + Sync + 'static {
+        Server {
+            thread_pool,
+            handler: Arc::new(handler),
+            middleware: MiddlewareChain::new(),
+            tls_config: None,
+            https_redirect_port: None,
+            connection_limits: None,
+            metrics: None,
+            health: Arc::new(Health::new()),
+            keep_alive: Some(Duration::from_secs(5)),
+            accepting: AtomicBool::new(true)
+        }
+    }
+
+    /// Adds middleware around every request, in the order added.
+    pub fn with_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn with_tls(mut self, tls_config: Arc<rustls::ServerConfig>) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    /// Answers every plaintext request with a redirect to the HTTPS listener on `port`.
+    pub fn redirect_to_https(mut self, port: u16) -> Self {
+        self.https_redirect_port = Some(port);
+        self
+    }
+
+    /// Turns away connections over the limits before they reach the thread pool.
+    pub fn with_connection_limits(mut self, connection_limits: Arc<ConnectionLimits>) -> Self {
+        self.connection_limits = Some(connection_limits);
+        self
+    }
+
+    /// Records requests, connections and traffic in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Reports readiness in `health`, which the server's `ServerHandle` clears on shutdown.
+    pub fn with_health(mut self, health: Arc<Health>) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// How long an idle connection is kept open for its next request; `None` closes every
+    /// connection after one response. Five seconds by default.
+    pub fn with_keep_alive(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.keep_alive = idle_timeout;
+        self
+    }
+
+    // No permit is needed without limits; `Err` hands the stream back when a limit is reached.
+    pub(crate) fn acquire_permit<S>(&self, stream: S, remote_addr: Option<SocketAddr>) -> Result<(S, Option<ConnectionPermit>), S> {
+        match &self.connection_limits {
+            Some(limits) => match limits.acquire(remote_addr.map(|addr| addr.ip())) {
+                Some(permit) => Ok((stream, Some(permit))),
+                None => Err(stream)
+            },
+            None => Ok((stream, None))
+        }
+    }
+
+    // Written from the accept loop, so the caller sets a write timeout to keep a slow client from holding it up.
+    fn reject_connection(mut stream: impl Write) {
+        let _ = get_too_many_requests_response(1).with_header("Connection", "close").write_to(&mut stream);
+    }
+
+    pub fn handle_connection(&self, stream: TcpStream) {
+        let remote_addr = stream.peer_addr().ok();
+        match self.acquire_permit(stream, remote_addr) {
+            Ok((stream, permit)) => self.handle_buffered_connection(stream, Vec::new(), permit, 0, None),
+            Err(stream) => {
+                let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                Server::reject_connection(stream);
+            }
+        }
+    }
+
+    /// Serves a connection from a Unix domain socket, which has no remote address.
+    #[cfg(unix)]
+    pub fn handle_unix_connection(&self, stream: UnixStream) {
+        match self.acquire_permit(stream, None) {
+            Ok((stream, permit)) => self.spawn_connection(stream, None, Vec::new(), permit, 0, None),
+            Err(stream) => {
+                let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                Server::reject_connection(stream);
+            }
+        }
+    }
+
+    pub fn handle_accepted(&self, connection: Connection) {
+        match connection {
+            Connection::Tcp(stream) => self.handle_connection(stream),
+            #[cfg(unix)]
+            Connection::Unix(stream) => self.handle_unix_connection(stream)
+        }
+    }
+
+    /// Accepts and serves connections from `listener` on the current thread, until `stop_accepting`.
+    pub fn serve(&self, listener: &Listener) {
+        loop {
+            let accepted = listener.accept();
+            if !self.is_accepting() {
+                return;
+            }
+            match accepted {
+                Ok(connection) => self.handle_accepted(connection),
+                Err(error) => println!("Failed to accept connection: {error}")
+            }
+        }
+    }
+
+    /// Serves a connection whose first bytes, `buffered`, have already been read from it, after
+    /// `served` earlier requests. With `park`, the connection is given back after each response.
+    pub(crate) fn handle_buffered_connection(&self, stream: TcpStream, buffered: Vec<u8>, permit: Option<ConnectionPermit>, served: usize, park: Option<Park<TcpStream>>) {
+        let remote_addr = stream.peer_addr().ok();
+        // A body written after its head would otherwise wait for the client to acknowledge the head.
+        let _ = stream.set_nodelay(true);
+        self.spawn_connection(stream, remote_addr, buffered, permit, served, park);
+    }
+
+    pub(crate) fn keep_alive(&self) -> Option<Duration> {
+        self.keep_alive
+    }
+
+    fn spawn_connection<S: Read + SendFile + ReadTimeout + Send + 'static>(&self, stream: S, remote_addr: Option<SocketAddr>, buffered: Vec<u8>, permit: Option<ConnectionPermit>, mut served: usize, mut park: Option<Park<S>>) {
+        let handler = Arc::clone(&self.handler);
+        let middleware = self.middleware.clone();
+        let https_redirect_port = self.https_redirect_port;
+        let keep_alive = self.keep_alive;
+        let metrics = self.metrics.clone();
+        let open_connection = metrics.as_ref().map(|metrics| metrics.open_connection());
+
+        self.thread_pool.execute(move || {
+            let _open_connection = open_connection;
+            let _ = stream.set_read_timeout(Some(FIRST_REQUEST_TIMEOUT));
+            let stream = PrefixedStream { prefix: io::Cursor::new(buffered), stream };
+            let mut stream = BufferedStream(BufReader::new(CountedStream { stream, metrics: metrics.clone() }));
+            let handler = |request: &mut Request| match https_redirect_port {
+                Some(port) => middleware.run(request, &|request| get_https_redirect_response(request, port)),
+                None => middleware.run(request, handler.as_ref())
+            };
+
+            loop {
+                served += 1;
+                let reusable = keep_alive.is_some() && served < MAX_REQUESTS_PER_CONNECTION;
+                if !serve_request(&mut stream, reusable, remote_addr, false, metrics.as_deref(), &handler) {
+                    return;
+                }
+                if let Some(park) = park.take() {
+                    let (stream, unread) = stream.into_parts();
+                    park(stream, unread, permit, served);
+                    return;
+                }
+                let _ = stream.set_read_timeout(keep_alive);
+            }
+        });
+    }
+
+    pub fn handle_tls_connection(&self, stream: TcpStream) {
+        let Some(tls_config) = self.tls_config.as_ref().map(Arc::clone) else {
+            println!("TLS connection rejected: no TLS configuration.");
+            return;
+        };
+        let remote_addr = stream.peer_addr().ok();
+        // A plaintext 429 would be garbage to a TLS client, so over the limit the connection is just closed.
+        let Ok((stream, permit)) = self.acquire_permit(stream, remote_addr) else {
+            return;
+        };
+        let _ = stream.set_nodelay(true);
+        let handler = Arc::clone(&self.handler);
+        let middleware = self.middleware.clone();
+        let keep_alive = self.keep_alive;
+        let metrics = self.metrics.clone();
+        let open_connection = metrics.as_ref().map(|metrics| metrics.open_connection());
+
+        self.thread_pool.execute(move || {
+            let (_permit, _open_connection) = (permit, open_connection);
+            let _ = stream.set_read_timeout(Some(FIRST_REQUEST_TIMEOUT));
+            match ServerConnection::new(tls_config) {
+                Ok(connection) => {
+                    let mut stream = BufferedStream(BufReader::new(StreamOwned::new(connection, CountedStream { stream, metrics: metrics.clone() })));
+                    for served in 1..=MAX_REQUESTS_PER_CONNECTION {
+                        let reusable = keep_alive.is_some() && served < MAX_REQUESTS_PER_CONNECTION;
+                        if !serve_request(&mut stream, reusable, remote_addr, true, metrics.as_deref(), &|request: &mut Request| middleware.run(request, handler.as_ref())) {
+                            break;
+                        }
+                        let _ = stream.set_read_timeout(keep_alive);
+                    }
+
+                    let stream = stream.0.get_mut();
+                    stream.conn.send_close_notify();
+                    let _ = stream.flush();
+                },
+                Err(error) => println!("TLS connection failed: {error}")
+            }
+        });
+    }
+
+    /// Makes `serve` and `Reactor::run` return instead of accepting more connections, and the
+    /// reactor close kept-alive connections waiting for another request. Connections in progress are
+    /// served to the end. A thread blocked accepting only notices once `Listener::wake` connects to it.
+    pub fn stop_accepting(&self) {
+        self.accepting.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn metrics(&self) -> Option<&Arc<Metrics>> {
+        self.metrics.as_ref()
+    }
+
+    /// Binds `addr` (port 0 picks a free port) and serves plaintext connections on a background thread.
+    pub fn start(self, addr: impl ToSocketAddrs) -> io::Result<ServerHandle> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let health = Arc::clone(&self.health);
+
+        let accept_shutting_down = Arc::clone(&shutting_down);
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_shutting_down.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                match stream {
+                    Ok(stream) => self.handle_connection(stream),
+                    Err(error) => println!("Failed to accept connection: {error}")
+                }
+            }
+        });
+
+        Ok(ServerHandle { local_addr, shutting_down, health, thread: Some(thread) })
+    }
+}
+
+/// A server started with `Server::start`; shuts it down when dropped.
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    shutting_down: Arc<AtomicBool>,
+    health: Arc<Health>,
+    thread: Option<thread::JoinHandle<()>>
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting connections and waits for the ones in progress to finish.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    /// Reports not ready, keeps serving for `drain` so load balancers can notice, then shuts down.
+    pub fn shutdown_gracefully(mut self, drain: Duration) {
+        self.health.set_ready(false);
+        thread::sleep(drain);
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        self.health.set_ready(false);
+        self.shutting_down.store(true, Ordering::SeqCst);
+
+        // The accept loop only notices the flag once another connection arrives.
+        wake_tcp_listener(self.local_addr);
+        let _ = thread.join();
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl<S: Read + Write + ReadTimeout> ReadTimeout for StreamOwned<ServerConnection, S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+}
+
+// Replays bytes that were read before the connection was handed over, then reads from the stream.
+struct PrefixedStream<S: Read + Write> {
+    prefix: io::Cursor<Vec<u8>>,
+    stream: S
+}
+
+impl<S: Read + Write> Read for PrefixedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.prefix.read(buf)? {
+            0 => self.stream.read(buf),
+            read => Ok(read)
+        }
+    }
+}
+
+impl<S: Read + Write> Write for PrefixedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: Read + SendFile> SendFile for PrefixedStream<S> {
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<()> {
+        self.stream.send_file(file, length)
+    }
+}
+
+impl<S: Read + Write + ReadTimeout> ReadTimeout for PrefixedStream<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+// Counts the bytes going through a connection for the server's metrics.
+struct CountedStream<S: Read + Write> {
+    stream: S,
+    metrics: Option<Arc<Metrics>>
+}
+
+impl<S: Read + Write> Read for CountedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stream.read(buf)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.add_bytes_received(read);
+        }
+        Ok(read)
+    }
+}
+
+impl<S: Read + Write> Write for CountedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.stream.write(buf)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.add_bytes_sent(written);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<S: Read + SendFile> SendFile for CountedStream<S> {
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<()> {
+        self.stream.send_file(file, length)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.add_bytes_sent(length as usize);
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write + ReadTimeout> ReadTimeout for CountedStream<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+// Keeps bytes read past the request head available to whoever takes over the connection.
+struct BufferedStream<S: Read + Write>(BufReader<S>);
+
+impl<S: Read + Write> BufferedStream<CountedStream<PrefixedStream<S>>> {
+    // Gives back the underlying stream with whatever was read from it but not yet consumed.
+    fn into_parts(self) -> (S, Vec<u8>) {
+        let mut unread = self.0.buffer().to_vec();
+        let prefixed = self.0.into_inner().stream;
+        let position = prefixed.prefix.position() as usize;
+        unread.extend_from_slice(&prefixed.prefix.get_ref()[position..]);
+        (prefixed.stream, unread)
+    }
+}
+
+impl<S: Read + Write> Read for BufferedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<S: Read + Write> BufRead for BufferedStream<S> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.0.consume(amount)
+    }
+}
+
+impl<S: Read + Write> Write for BufferedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.get_mut().flush()
+    }
+}
+
+impl<S: Read + SendFile> SendFile for BufferedStream<S> {
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<()> {
+        self.0.get_mut().send_file(file, length)
+    }
+}
+
+impl<S: Read + Write + ReadTimeout> ReadTimeout for BufferedStream<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.0.get_ref().set_read_timeout(timeout)
+    }
+}
+
+// Sends `100 Continue` before the body is first read, so a client waiting on `Expect: 100-continue`
+// only sends its body once the request has got as far as a handler that wants it.
+struct ExpectContinue<'a, S: Read + Write> {
+    stream: &'a mut BufferedStream<S>,
+    pending: Rc<Cell<bool>>
+}
+
+impl<S: Read + Write> ExpectContinue<'_, S> {
+    fn send_continue(&mut self) -> io::Result<()> {
+        if self.pending.replace(false) {
+            self.stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            self.stream.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for ExpectContinue<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.send_continue()?;
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Read + Write> BufRead for ExpectContinue<'_, S> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.send_continue()?;
+        self.stream.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.stream.consume(amount)
+    }
+}
+
+fn wants_keep_alive(request: &Request) -> bool {
+    let connection = request.header("Connection");
+    match request.version.as_str() {
+        "HTTP/1.1" => !has_token(connection, "close"),
+        "HTTP/1.0" => has_token(connection, "keep-alive"),
+        _ => false
+    }
+}
+
+// Every `Content-Length` given, each of a comma-separated list included, has to be the same, or the
+// client and a proxy in between could disagree about where the body ends. `None` when they are not.
+fn get_content_length(request: &Request) -> Option<u64> {
+    let mut lengths = request.headers.get_all("Content-Length").flat_map(|value| value.split(',')).map(str::trim);
+    let Some(first) = lengths.next() else {
+        return Some(0);
+    };
+    if first.is_empty() || !first.bytes().all(|byte| byte.is_ascii_digit()) || lengths.any(|length| length != first) {
+        return None;
+    }
+    first.parse().ok()
+}
+
+// Returns the response and whether the request body was read to the end, which the connection needs to be reused.
+fn dispatch<'a, S: Read + Write>(mut request: Request<'a>, stream: &'a mut BufferedStream<S>, handler: &dyn Fn(&mut Request) -> HttpResponse) -> (HttpResponse, bool) {
+    // HTTP/1.0 clients predate `Expect` and are not waiting for an answer to it.
+    let expects_continue = match request.header("Expect") {
+        Some(expectation) if request.version == "HTTP/1.1" => match expectation.eq_ignore_ascii_case("100-continue") {
+            true => true,
+            false => return (HttpResponse::error(417), false)
+        },
+        _ => false
+    };
+    let continue_pending = Rc::new(Cell::new(false));
+    let stream = ExpectContinue { stream, pending: Rc::clone(&continue_pending) };
+
+    match request.header("Transfer-Encoding") {
+        Some(coding) if coding.eq_ignore_ascii_case("chunked") => {
+            // Both would let the client and any proxy in between disagree about where the body ends.
+            if request.headers.contains("Content-Length") {
+                return (HttpResponse::bad_request(), false);
+            }
+            continue_pending.set(expects_continue);
+            let body = ChunkedReader::new(stream);
+            request.trailers = body.trailers();
+            request.body = Box::new(body);
+        },
+        Some(_) => return (HttpResponse::error(501), false),
+        None => {
+            let Some(content_length) = get_content_length(&request) else {
+                return (HttpResponse::bad_request(), false);
+            };
+            continue_pending.set(expects_continue && content_length > 0);
+            request.body = Box::new(stream.take(content_length));
+        }
+    }
+
+    let response = handler(&mut request);
+    // A client still waiting to send its body may send it anyway, so the connection is not reused.
+    if continue_pending.get() {
+        return (response, false);
+    }
+    let drained = io::copy(&mut (&mut request.body).take(MAX_DRAINED_BODY + 1), &mut io::sink())
+        .is_ok_and(|discarded| discarded <= MAX_DRAINED_BODY);
+
+    (response, drained)
+}
+
+fn measure(metrics: Option<&Metrics>, request: &mut Request, handler: &dyn Fn(&mut Request) -> HttpResponse) -> HttpResponse {
+    let Some(metrics) = metrics else {
+        return handler(request);
+    };
+
+    let start = Instant::now();
+    let response = handler(request);
+    metrics.record_request(&request.method, request.route.as_deref(), response.status, start.elapsed());
+
+    response
+}
+
+// Frames the body so the connection can be reused when `keep_alive` allows, and says whether it can.
+fn prepare_response(mut response: HttpResponse, method: &str, version: &str, mut keep_alive: bool) -> (HttpResponse, bool) {
+    // The upgrade takes the connection over once the head is written.
+    if response.upgrade.is_some() {
+        return (response, false);
+    }
+    keep_alive &= !has_token(response.headers.get("Connection"), "close");
+
+    if method == "HEAD" {
+        response = response.without_body();
+    } else if matches!(response.body, Body::Stream(_)) && !response.headers.contains("Content-Length") && !response.headers.contains("Transfer-Encoding") {
+        match version {
+            "HTTP/1.1" => response = response.into_chunked(),
+            _ => keep_alive = false
+        }
+    }
+
+    if !keep_alive {
+        response.headers.insert("Connection", "close");
+    } else if version == "HTTP/1.0" {
+        response.headers.insert("Connection", "keep-alive");
+    }
+
+    (response, keep_alive)
+}
+
+// Serves one request, saying whether the connection can be used for another.
+fn serve_request<S: Read + SendFile + ReadTimeout>(stream: &mut BufferedStream<S>, reusable: bool, remote_addr: Option<SocketAddr>, secure: bool, metrics: Option<&Metrics>, handler: &dyn Fn(&mut Request) -> HttpResponse) -> bool {
+    let (mut response, reuse) = match read_request(stream) {
+        Ok(mut request) => {
+            let _ = stream.set_read_timeout(None);
+            request.remote_addr = remote_addr;
+            request.secure = secure;
+
+            let (method, version) = (request.method.clone(), request.version.clone());
+            let reuse = reusable && wants_keep_alive(&request);
+            let (response, drained) = dispatch(request, stream, &|request: &mut Request| measure(metrics, request, handler));
+            prepare_response(response, &method, &version, reuse && drained)
+        },
+        Err(RequestError::Malformed) => prepare_response(HttpResponse::bad_request(), "", "", false),
+        Err(RequestError::HeadTooLarge) => prepare_response(HttpResponse::error(431), "", "", false),
+        Err(_) => return false
+    };
+
+    let upgrade = response.upgrade.take();
+    if let Err(error) = response.send_to(stream) {
+        println!("Failed to write response: {error}");
+        return false;
+    }
+
+    if let Some(upgrade) = upgrade {
+        upgrade(stream);
+        return false;
+    }
+    reuse
+}
+
+fn get_https_redirect_response(request: &Request, https_port: u16) -> HttpResponse {
+    let Some(host) = request.host() else {
+        return HttpResponse::bad_request();
+    };
+
+    let host_name = strip_port(host);
+    let authority = match https_port {
+        443 => host_name.to_string(),
+        port => format!("{host_name}:{port}")
+    };
+
+    // 308 keeps the method and body for anything that is not a plain GET or HEAD.
+    let status = match request.method.as_str() {
+        "GET" | "HEAD" => 301,
+        _ => 308
+    };
+
+    HttpResponse::redirect(status, &format!("https://{authority}{}", request.target()))
+}
@@ -0,0 +1,279 @@
+use std::{collections::{BTreeMap, HashMap}, fs, io, path::PathBuf, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use crate::{cookie::{Cookie, SameSite}, http::{HttpResponse, Request}};
+
+pub type SessionData = BTreeMap<String, String>;
+
+/// Where session data lives between requests. Entries past their expiry must not be returned.
+pub trait SessionStore: Send + Sync {
+    fn load(&self, id: &str) -> Option<SessionData>;
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> io::Result<()>;
+    fn remove(&self, id: &str) -> io::Result<()>;
+    fn remove_expired(&self) -> io::Result<()>;
+}
+
+fn get_unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+// How often, in seconds, `MemoryStore` sweeps out expired sessions as it is used.
+const PURGE_INTERVAL: u64 = 60;
+
+/// Keeps sessions in memory, sweeping out expired ones about once a minute as sessions are loaded and saved.
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (u64, SessionData)>>,
+    next_purge: AtomicU64
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    // Called with the sessions locked, so only one caller sweeps.
+    fn purge_if_due(&self, sessions: &mut HashMap<String, (u64, SessionData)>) {
+        let now = get_unix_time();
+        if now >= self.next_purge.load(Ordering::Relaxed) {
+            self.next_purge.store(now + PURGE_INTERVAL, Ordering::Relaxed);
+            sessions.retain(|_, (expires, _)| *expires > now);
+        }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.purge_if_due(&mut sessions);
+        sessions.get(id)
+            .filter(|(expires, _)| *expires > get_unix_time())
+            .map(|(_, data)| data.clone())
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> io::Result<()> {
+        let expires = get_unix_time() + ttl.as_secs();
+        let mut sessions = self.sessions.lock().unwrap();
+        self.purge_if_due(&mut sessions);
+        sessions.insert(id.to_string(), (expires, data.clone()));
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn remove_expired(&self) -> io::Result<()> {
+        let now = get_unix_time();
+        self.sessions.lock().unwrap().retain(|_, (expires, _)| *expires > now);
+        Ok(())
+    }
+}
+
+/// One file per session in `directory`: the expiry as Unix seconds on the first line, then the data as JSON.
+pub struct FileStore {
+    directory: PathBuf
+}
+
+impl FileStore {
+    pub fn new(directory: impl Into<PathBuf>) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(FileStore { directory })
+    }
+
+    // Ids come from clients, so anything that is not one of ours never reaches the file system.
+    fn get_path(&self, id: &str) -> Option<PathBuf> {
+        let is_valid = id.len() == 2 * ID_LENGTH && id.bytes().all(|byte| byte.is_ascii_hexdigit());
+        is_valid.then(|| self.directory.join(id))
+    }
+
+    fn read(path: &PathBuf) -> Option<(u64, SessionData)> {
+        let contents = fs::read_to_string(path).ok()?;
+        let (expires, data) = contents.split_once('\n')?;
+        Some((expires.parse().ok()?, serde_json::from_str(data).ok()?))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let (expires, data) = FileStore::read(&self.get_path(id)?)?;
+        (expires > get_unix_time()).then_some(data)
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> io::Result<()> {
+        let path = self.get_path(id).ok_or(io::ErrorKind::InvalidInput)?;
+        let expires = get_unix_time() + ttl.as_secs();
+        let contents = format!("{expires}\n{}", serde_json::to_string(data)?);
+
+        // Write then rename so a concurrent load never sees half a file.
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, contents)?;
+        fs::rename(temporary_path, path)
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        match self.get_path(id).map(fs::remove_file) {
+            Some(Err(error)) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(())
+        }
+    }
+
+    fn remove_expired(&self) -> io::Result<()> {
+        let now = get_unix_time();
+        for entry in fs::read_dir(&self.directory)?.flatten() {
+            let path = entry.path();
+            if FileStore::read(&path).is_none_or(|(expires, _)| expires <= now) {
+                let _ = fs::remove_file(path);
+            }
+        }
+        Ok(())
+    }
+}
+
+const ID_LENGTH: usize = 32;
+
+fn generate_id() -> String {
+    let mut bytes = [0u8; ID_LENGTH];
+    getrandom::getrandom(&mut bytes).expect("Failed to get random bytes for a session id");
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// A request's session, obtained from `Sessions::load` and written back by `Sessions::save`.
+pub struct Session {
+    id: Option<String>,
+    previous_id: Option<String>,
+    data: SessionData,
+    changed: bool,
+    destroyed: bool
+}
+
+impl Session {
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn is_new(&self) -> bool {
+        self.id.is_none()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data.get(key).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.data.insert(key.to_string(), value.to_string());
+        self.changed = true;
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.changed = true;
+        self.data.remove(key)
+    }
+
+    /// Gives the session a fresh id on save, e.g. after logging in.
+    pub fn regenerate_id(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.previous_id.get_or_insert(id);
+        }
+        self.changed = true;
+    }
+
+    /// Deletes the session from the store and the client on save.
+    pub fn destroy(&mut self) {
+        self.data.clear();
+        self.destroyed = true;
+    }
+}
+
+/// Ties a `SessionStore` to the session cookie.
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    same_site: SameSite
+}
+
+impl Sessions {
+    pub fn new(store: Arc<dyn SessionStore>) -> Self {
+        Sessions {
+            store,
+            cookie_name: "session".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: false,
+            same_site: SameSite::Lax
+        }
+    }
+
+    pub fn cookie_name(mut self, cookie_name: &str) -> Self {
+        self.cookie_name = cookie_name.to_string();
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    pub fn store(&self) -> &Arc<dyn SessionStore> {
+        &self.store
+    }
+
+    /// The session named by the request's cookie, or a new empty one.
+    pub fn load(&self, request: &Request) -> Session {
+        let existing = request.cookie(&self.cookie_name)
+            .and_then(|id| self.store.load(&id).map(|data| (id, data)));
+
+        let (id, data) = match existing {
+            Some((id, data)) => (Some(id), data),
+            None => (None, SessionData::new())
+        };
+        Session { id, previous_id: None, data, changed: false, destroyed: false }
+    }
+
+    fn get_cookie(&self, value: &str) -> Cookie {
+        Cookie::new(&self.cookie_name, value)
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site)
+    }
+
+    /// Stores a changed session and sets or clears the cookie on `response`. A store failure gives a 500.
+    pub fn save(&self, session: Session, response: HttpResponse) -> HttpResponse {
+        let removed_ids = session.previous_id.iter().chain(session.id.iter().filter(|_| session.destroyed));
+        for id in removed_ids {
+            if self.store.remove(id).is_err() {
+                return HttpResponse::internal_server_error();
+            }
+        }
+
+        if session.destroyed {
+            return match session.id.or(session.previous_id) {
+                Some(_) => response.with_cookie(&self.get_cookie("").max_age(Duration::ZERO)),
+                None => response
+            };
+        }
+
+        if !session.changed {
+            return response;
+        }
+
+        let id = session.id.unwrap_or_else(generate_id);
+        if self.store.save(&id, &session.data, self.ttl).is_err() {
+            return HttpResponse::internal_server_error();
+        }
+
+        response.with_cookie(&self.get_cookie(&id).max_age(self.ttl))
+    }
+}
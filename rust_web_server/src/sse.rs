@@ -0,0 +1,106 @@
+use std::{io::{self, Write}, sync::mpsc::{Receiver, RecvTimeoutError}, time::Duration};
+use crate::http::{HttpResponse, Request};
+
+#[derive(Clone, Debug, Default)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    retry: Option<Duration>,
+    data: String
+}
+
+// Field values may not contain line breaks; anything after one would be parsed as a new field.
+fn single_line(value: &str) -> String {
+    value.chars().filter(|c| !matches!(c, '\r' | '\n' | '\0')).collect()
+}
+
+impl Event {
+    pub fn new(data: &str) -> Self {
+        Event { data: data.into(), ..Event::default() }
+    }
+
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(single_line(id));
+        self
+    }
+
+    pub fn event(mut self, event: &str) -> Self {
+        self.event = Some(single_line(event));
+        self
+    }
+
+    /// Reconnection delay the client should use after losing the stream.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn to_wire_format(&self) -> String {
+        let mut wire = String::new();
+        if let Some(id) = &self.id {
+            wire.push_str(&format!("id: {id}\n"));
+        }
+        if let Some(event) = &self.event {
+            wire.push_str(&format!("event: {event}\n"));
+        }
+        if let Some(retry) = self.retry {
+            wire.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+            wire.push_str(&format!("data: {line}\n"));
+        }
+        wire.push('\n');
+        wire
+    }
+}
+
+pub struct EventStream<'a> {
+    writer: &'a mut dyn Write,
+    last_event_id: Option<String>
+}
+
+impl EventStream<'_> {
+    /// The `Last-Event-ID` the client sent when reconnecting, used to resume the stream.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    pub fn send(&mut self, event: &Event) -> io::Result<()> {
+        self.writer.write_all(event.to_wire_format().as_bytes())?;
+        self.writer.flush()
+    }
+
+    pub fn comment(&mut self, text: &str) -> io::Result<()> {
+        self.writer.write_all(format!(": {}\n\n", single_line(text)).as_bytes())?;
+        self.writer.flush()
+    }
+
+    /// A comment line that keeps proxies from timing out an idle stream.
+    pub fn keep_alive(&mut self) -> io::Result<()> {
+        self.writer.write_all(b":\n\n")?;
+        self.writer.flush()
+    }
+
+    /// Forwards events from `events` until the sender hangs up or the client goes away,
+    /// sending a keep-alive comment whenever nothing was sent for `keep_alive_interval`.
+    pub fn forward(&mut self, events: Receiver<Event>, keep_alive_interval: Duration) -> io::Result<()> {
+        loop {
+            match events.recv_timeout(keep_alive_interval) {
+                Ok(event) => self.send(&event)?,
+                Err(RecvTimeoutError::Timeout) => self.keep_alive()?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(())
+            }
+        }
+    }
+}
+
+/// Answers `request` with a `text/event-stream` response whose events are produced by `handler`.
+pub fn event_stream<F>(request: &Request, handler: F) -> HttpResponse where F: FnOnce(&mut EventStream) -> io::Result<()> + Send + 'static {
+    let last_event_id = request.header("Last-Event-ID").map(String::from);
+
+    HttpResponse::stream(200, "text/event-stream", Box::new(move |writer| {
+        let mut events = EventStream { writer, last_event_id };
+        handler(&mut events)
+    }))
+    .with_header("Cache-Control", "no-cache")
+}
@@ -0,0 +1,38 @@
+use std::{fs::File, path::{Path, PathBuf}};
+use crate::http::{HttpResponse, Request};
+
+pub use crate::mime::get_content_type;
+
+/// Maps a request path onto `root`, refusing paths that would escape it.
+pub fn resolve_path(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for segment in request_path.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return None,
+            segment if segment.contains('\\') || segment.contains('\0') => return None,
+            segment => path.push(segment)
+        }
+    }
+
+    if path.is_dir() {
+        path.push("index.html");
+    }
+
+    Some(path)
+}
+
+/// Serves the file at the request path under `root`, sent straight from the file where possible.
+pub fn serve_file(root: &Path, request: &Request) -> Option<HttpResponse> {
+    if request.method != "GET" && request.method != "HEAD" {
+        return None;
+    }
+
+    let path = resolve_path(root, request.path())?;
+    let file = File::open(&path).ok()?;
+    if !file.metadata().ok()?.is_file() {
+        return None;
+    }
+
+    HttpResponse::file(200, get_content_type(&path), file).ok()
+}
@@ -0,0 +1,469 @@
+use std::{collections::{BTreeMap, HashMap}, io, path::PathBuf, sync::{Arc, Mutex}, time::SystemTime};
+use crate::{assets::Assets, http::HttpResponse};
+
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug)]
+pub enum TemplateError {
+    Io(PathBuf, io::Error),
+    Syntax(String, String),
+    RecursionLimit(String)
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>)
+}
+
+impl Value {
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(value) => *value,
+            Value::Int(value) => *value != 0,
+            Value::Float(value) => *value != 0.0,
+            Value::String(value) => !value.is_empty(),
+            Value::List(values) => !values.is_empty(),
+            Value::Map(values) => !values.is_empty()
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Value::Bool(value) => value.to_string(),
+            Value::Int(value) => value.to_string(),
+            Value::Float(value) => value.to_string(),
+            Value::String(value) => value.clone(),
+            Value::Null | Value::List(_) | Value::Map(_) => String::new()
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(values) => values.get(key),
+            Value::List(values) => key.parse::<usize>().ok().and_then(|index| values.get(index)),
+            _ => None
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::Int(value as i64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Value::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+/// The variables a template is rendered with.
+#[derive(Clone, Debug, Default)]
+pub struct Context(BTreeMap<String, Value>);
+
+impl Context {
+    pub fn new() -> Self {
+        Context(BTreeMap::new())
+    }
+
+    pub fn insert(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.0.insert(name.into(), value.into());
+        self
+    }
+}
+
+impl From<Context> for Value {
+    fn from(context: Context) -> Self {
+        Value::Map(context.0)
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Variable { path: Vec<String>, escape: bool },
+    If { negate: bool, path: Vec<String>, then: Vec<Node>, otherwise: Vec<Node> },
+    For { variable: String, path: Vec<String>, body: Vec<Node> },
+    Include(String),
+    Block(String, Vec<Node>)
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+    Tag(&'a str)
+}
+
+#[derive(Debug)]
+pub struct Template {
+    extends: Option<String>,
+    nodes: Vec<Node>
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = rest.find('{') {
+        let (close, kind) = match rest[start..].get(..2) {
+            Some("{{") => ("}}", 0),
+            Some("{%") => ("%}", 1),
+            Some("{#") => ("#}", 2),
+            _ => {
+                tokens.push(Token::Text(&rest[..=start]));
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+
+        if start > 0 {
+            tokens.push(Token::Text(&rest[..start]));
+        }
+
+        let inner = &rest[start + 2..];
+        let end = inner.find(close).ok_or_else(|| format!("unclosed `{}`", &rest[start..start + 2]))?;
+        match kind {
+            0 => tokens.push(Token::Variable(inner[..end].trim())),
+            1 => tokens.push(Token::Tag(inner[..end].trim())),
+            _ => {}
+        }
+        rest = &inner[end + 2..];
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+
+    Ok(tokens)
+}
+
+fn parse_path(expression: &str) -> Result<Vec<String>, String> {
+    let path: Vec<String> = expression.split('.').map(|part| part.trim().to_string()).collect();
+    let valid = path.iter().all(|part| !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '_'));
+
+    if valid { Ok(path) } else { Err(format!("invalid expression `{expression}`")) }
+}
+
+fn parse_quoted(argument: &str) -> Result<String, String> {
+    argument.strip_prefix('"').and_then(|argument| argument.strip_suffix('"'))
+        .map(String::from)
+        .ok_or_else(|| format!("expected a quoted name, found `{argument}`"))
+}
+
+struct Parser<'a> {
+    tokens: std::vec::IntoIter<Token<'a>>,
+    extends: Option<String>
+}
+
+impl Parser<'_> {
+    // Parses nodes until one of `terminators` closes the current block, returning that tag.
+    fn parse_until(&mut self, terminators: &[&str]) -> Result<(Vec<Node>, Option<String>), String> {
+        let mut nodes = Vec::new();
+
+        while let Some(token) = self.tokens.next() {
+            let tag = match token {
+                Token::Text(text) => {
+                    nodes.push(Node::Text(text.into()));
+                    continue;
+                },
+                Token::Variable(expression) => {
+                    let mut parts = expression.split('|').map(str::trim);
+                    let path = parse_path(parts.next().unwrap_or_default())?;
+                    let mut escape = true;
+                    for filter in parts {
+                        match filter {
+                            "safe" => escape = false,
+                            _ => return Err(format!("unknown filter `{filter}`"))
+                        }
+                    }
+                    nodes.push(Node::Variable { path, escape });
+                    continue;
+                },
+                Token::Tag(tag) => tag
+            };
+
+            let (keyword, argument) = tag.split_once(' ').map_or((tag, ""), |(keyword, argument)| (keyword, argument.trim()));
+            if terminators.contains(&keyword) {
+                return Ok((nodes, Some(keyword.into())));
+            }
+
+            match keyword {
+                "if" => {
+                    let (negate, expression) = match argument.strip_prefix("not ") {
+                        Some(expression) => (true, expression),
+                        None => (false, argument)
+                    };
+                    let (then, end) = self.parse_until(&["else", "endif"])?;
+                    let otherwise = match end.as_deref() {
+                        Some("else") => self.parse_until(&["endif"])?.0,
+                        _ => Vec::new()
+                    };
+                    nodes.push(Node::If { negate, path: parse_path(expression)?, then, otherwise });
+                },
+                "for" => {
+                    let Some((variable, expression)) = argument.split_once(" in ") else {
+                        return Err(format!("expected `for <name> in <expression>`, found `{tag}`"));
+                    };
+                    let (body, _) = self.parse_until(&["endfor"])?;
+                    nodes.push(Node::For { variable: variable.trim().into(), path: parse_path(expression)?, body });
+                },
+                "include" => nodes.push(Node::Include(parse_quoted(argument)?)),
+                "extends" => self.extends = Some(parse_quoted(argument)?),
+                "block" => {
+                    let (body, _) = self.parse_until(&["endblock"])?;
+                    nodes.push(Node::Block(argument.into(), body));
+                },
+                _ => return Err(format!("unknown tag `{tag}`"))
+            }
+        }
+
+        match terminators.last() {
+            Some(terminator) => Err(format!("missing `{{% {terminator} %}}`")),
+            None => Ok((nodes, None))
+        }
+    }
+}
+
+impl Template {
+    pub fn compile(source: &str) -> Result<Template, String> {
+        let mut parser = Parser { tokens: tokenize(source)?.into_iter(), extends: None };
+        let (nodes, _) = parser.parse_until(&[])?;
+
+        Ok(Template { extends: parser.extends, nodes })
+    }
+}
+
+struct CachedTemplate {
+    template: Arc<Template>,
+    modified: Option<SystemTime>
+}
+
+/// Templates loaded from the assets, compiled on first use and, when read from disk,
+/// recompiled when the file changes.
+pub struct Templates {
+    assets: Assets,
+    cache: Mutex<HashMap<String, CachedTemplate>>
+}
+
+struct Renderer<'a> {
+    templates: &'a Templates,
+    context: &'a Value,
+    scopes: Vec<(String, Value)>,
+    depth: usize
+}
+
+impl Templates {
+    /// Templates read from `directory` on disk.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Templates::from_assets(Assets::Disk(directory.into()))
+    }
+
+    pub fn from_assets(assets: Assets) -> Self {
+        Templates { assets, cache: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        let modified = self.assets.modified(name);
+
+        if let Some(cached) = self.cache.lock().unwrap().get(name) {
+            let is_embedded = matches!(self.assets, Assets::Embedded);
+            if is_embedded || modified.is_some() && cached.modified == modified {
+                return Ok(Arc::clone(&cached.template));
+            }
+        }
+
+        let source = self.assets.get(name)
+            .and_then(|asset| String::from_utf8(asset.contents.into_owned()).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)))
+            .map_err(|error| TemplateError::Io(PathBuf::from(name), error))?;
+        let template = Arc::new(Template::compile(&source).map_err(|message| TemplateError::Syntax(name.into(), message))?);
+
+        let cached = CachedTemplate { template: Arc::clone(&template), modified };
+        self.cache.lock().unwrap().insert(name.into(), cached);
+
+        Ok(template)
+    }
+
+    pub fn render(&self, name: &str, context: impl Into<Value>) -> Result<String, TemplateError> {
+        let context = context.into();
+        let mut renderer = Renderer { templates: self, context: &context, scopes: Vec::new(), depth: 0 };
+
+        let mut output = String::new();
+        renderer.render_template(name, &HashMap::new(), &mut output)?;
+        Ok(output)
+    }
+
+    /// Renders `name` into an HTML response, or a 500 if rendering fails.
+    pub fn response(&self, status: u16, name: &str, context: impl Into<Value>) -> HttpResponse {
+        match self.render(name, context) {
+            Ok(contents) => HttpResponse::html(status, contents),
+            Err(error) => {
+                println!("Failed to render template {name}: {error:?}");
+                HttpResponse::internal_server_error()
+            }
+        }
+    }
+}
+
+impl Renderer<'_> {
+    fn lookup(&self, path: &[String]) -> Value {
+        let Some((first, rest)) = path.split_first() else {
+            return Value::Null;
+        };
+
+        let root = self.scopes.iter().rev()
+            .find(|(name, _)| name == first)
+            .map(|(_, value)| value)
+            .or_else(|| self.context.get(first));
+
+        rest.iter()
+            .try_fold(root, |value, key| Some(value?.get(key)))
+            .flatten()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn render_template(&mut self, name: &str, blocks: &HashMap<String, Arc<Template>>, output: &mut String) -> Result<(), TemplateError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(TemplateError::RecursionLimit(name.into()));
+        }
+        self.depth += 1;
+
+        let template = self.templates.get(name)?;
+        let result = match &template.extends {
+            Some(parent) => {
+                // Blocks from the most derived template win, so only add ones not overridden yet.
+                let mut blocks = blocks.clone();
+                for block_name in get_block_names(&template.nodes) {
+                    blocks.entry(block_name.to_string()).or_insert_with(|| Arc::clone(&template));
+                }
+                self.render_template(parent, &blocks, output)
+            },
+            None => self.render_nodes(&template.nodes, blocks, output)
+        };
+
+        self.depth -= 1;
+        result
+    }
+
+    fn render_nodes(&mut self, nodes: &[Node], blocks: &HashMap<String, Arc<Template>>, output: &mut String) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => output.push_str(text),
+                Node::Variable { path, escape: true } => output.push_str(&escape_html(&self.lookup(path).to_text())),
+                Node::Variable { path, escape: false } => output.push_str(&self.lookup(path).to_text()),
+                Node::If { negate, path, then, otherwise } => {
+                    let branch = if self.lookup(path).is_truthy() != *negate { then } else { otherwise };
+                    self.render_nodes(branch, blocks, output)?;
+                },
+                Node::For { variable, path, body } => {
+                    let Value::List(items) = self.lookup(path) else {
+                        continue;
+                    };
+
+                    let length = items.len();
+                    for (index, item) in items.into_iter().enumerate() {
+                        let loop_info = Context::new()
+                            .insert("index", index + 1)
+                            .insert("first", index == 0)
+                            .insert("last", index + 1 == length);
+                        self.scopes.push(("loop".into(), loop_info.into()));
+                        self.scopes.push((variable.clone(), item));
+
+                        let result = self.render_nodes(body, blocks, output);
+                        self.scopes.truncate(self.scopes.len() - 2);
+                        result?;
+                    }
+                },
+                Node::Include(name) => self.render_template(name, &HashMap::new(), output)?,
+                Node::Block(name, body) => {
+                    let template = blocks.get(name).map(Arc::clone);
+                    let body = template.as_deref()
+                        .and_then(|template| find_block(&template.nodes, name))
+                        .unwrap_or(body);
+                    self.render_nodes(body, blocks, output)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Blocks may sit inside `if`, `for` or other blocks, so both of these look through every level.
+fn find_block<'a>(nodes: &'a [Node], name: &str) -> Option<&'a [Node]> {
+    nodes.iter().find_map(|node| match node {
+        Node::Block(block_name, body) if block_name == name => Some(body.as_slice()),
+        Node::Block(_, body) | Node::For { body, .. } => find_block(body, name),
+        Node::If { then, otherwise, .. } => find_block(then, name).or_else(|| find_block(otherwise, name)),
+        _ => None
+    })
+}
+
+fn get_block_names(nodes: &[Node]) -> Vec<&str> {
+    nodes.iter().flat_map(|node| match node {
+        Node::Block(name, body) => [vec![name.as_str()], get_block_names(body)].concat(),
+        Node::For { body, .. } => get_block_names(body),
+        Node::If { then, otherwise, .. } => [get_block_names(then), get_block_names(otherwise)].concat(),
+        _ => Vec::new()
+    }).collect()
+}
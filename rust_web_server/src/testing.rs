@@ -0,0 +1,147 @@
+use std::{io::{self, BufReader, Read, Write}, net::{SocketAddr, TcpStream}, time::Duration};
+use serde::{de::DeserializeOwned, Serialize};
+use crate::{chunked::ChunkedReader, http::{read_response_head, Headers}, url::percent_encode};
+
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>
+}
+
+impl TestResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+/// A minimal HTTP/1.1 client for tests: one connection per request, closed afterwards.
+pub struct TestClient {
+    addr: SocketAddr,
+    timeout: Duration
+}
+
+impl TestClient {
+    pub fn new(addr: SocketAddr) -> Self {
+        TestClient { addr, timeout: Duration::from_secs(10) }
+    }
+
+    /// How long to wait on the server before failing with `TimedOut` or `WouldBlock`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// A connection of your own, for protocols the client does not speak such as WebSockets.
+    pub fn connect(&self) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect_timeout(&self.addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        Ok(stream)
+    }
+
+    pub fn request(&self, method: &str, path: &str) -> TestRequest<'_> {
+        TestRequest { client: self, method: method.to_string(), path: path.to_string(), headers: Headers::new(), body: Vec::new() }
+    }
+
+    pub fn get(&self, path: &str) -> io::Result<TestResponse> {
+        self.request("GET", path).send()
+    }
+
+    /// Sends `raw` exactly as given and reads one response.
+    pub fn send_raw(&self, raw: &[u8]) -> io::Result<TestResponse> {
+        let mut stream = self.connect()?;
+        stream.write_all(raw)?;
+        read_response(&mut BufReader::new(stream), raw.starts_with(b"HEAD "))
+    }
+}
+
+fn read_response<R: io::BufRead>(reader: &mut R, head_request: bool) -> io::Result<TestResponse> {
+    let (status, headers) = read_response_head(reader)?;
+
+    let mut body = Vec::new();
+    if !head_request && status != 204 && status != 304 {
+        if headers.get("Transfer-Encoding").is_some_and(|coding| coding.eq_ignore_ascii_case("chunked")) {
+            ChunkedReader::new(reader).read_to_end(&mut body)?;
+            return Ok(TestResponse { status, headers, body });
+        }
+
+        match headers.get("Content-Length").map(str::parse::<u64>) {
+            Some(Ok(length)) => {
+                reader.take(length).read_to_end(&mut body)?;
+                if (body.len() as u64) < length {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "response body cut short"));
+                }
+            },
+            Some(Err(_)) => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length")),
+            None => {
+                reader.read_to_end(&mut body)?;
+            }
+        }
+    }
+
+    Ok(TestResponse { status, headers, body })
+}
+
+pub struct TestRequest<'c> {
+    client: &'c TestClient,
+    method: String,
+    path: String,
+    headers: Headers,
+    body: Vec<u8>
+}
+
+impl TestRequest<'_> {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn json<T: Serialize + ?Sized>(mut self, value: &T) -> Self {
+        self.headers.insert("Content-Type", "application/json");
+        self.body = serde_json::to_vec(value).expect("Failed to serialize request body");
+        self
+    }
+
+    pub fn form(mut self, fields: &[(&str, &str)]) -> Self {
+        let body: Vec<String> = fields.iter()
+            .map(|(name, value)| format!("{}={}", percent_encode(name, false), percent_encode(value, false)))
+            .collect();
+        self.headers.insert("Content-Type", "application/x-www-form-urlencoded");
+        self.body = body.join("&").into_bytes();
+        self
+    }
+
+    pub fn send(self) -> io::Result<TestResponse> {
+        let mut raw = format!("{} {} HTTP/1.1\r\n", self.method, self.path);
+        if !self.headers.contains("Host") {
+            raw.push_str(&format!("Host: {}\r\n", self.client.addr));
+        }
+        if !self.body.is_empty() && !self.headers.contains("Content-Length") {
+            raw.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        if !self.headers.contains("Connection") {
+            raw.push_str("Connection: close\r\n");
+        }
+        for (name, value) in self.headers.iter() {
+            raw.push_str(&format!("{name}: {value}\r\n"));
+        }
+        raw.push_str("\r\n");
+
+        let mut raw = raw.into_bytes();
+        raw.extend_from_slice(&self.body);
+        self.client.send_raw(&raw)
+    }
+}
@@ -0,0 +1,126 @@
+use std::{collections::HashMap, fs::File, io::{self, BufReader}, path::{Path, PathBuf}, sync::Arc};
+use rustls::{crypto::ring, server::{ClientHello, ResolvesServerCert}, sign::CertifiedKey};
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(PathBuf, io::Error),
+    NoCertificates(PathBuf),
+    NoPrivateKey(PathBuf),
+    NoCertificateConfigured,
+    Rustls(rustls::Error)
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(error: rustls::Error) -> Self {
+        TlsError::Rustls(error)
+    }
+}
+
+fn open_pem_file(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|error| TlsError::Io(path.to_path_buf(), error))
+}
+
+pub fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<Arc<CertifiedKey>, TlsError> {
+    let certificates = rustls_pemfile::certs(&mut open_pem_file(cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| TlsError::Io(cert_path.to_path_buf(), error))?;
+    if certificates.is_empty() {
+        return Err(TlsError::NoCertificates(cert_path.to_path_buf()));
+    }
+
+    let key = rustls_pemfile::private_key(&mut open_pem_file(key_path)?)
+        .map_err(|error| TlsError::Io(key_path.to_path_buf(), error))?
+        .ok_or_else(|| TlsError::NoPrivateKey(key_path.to_path_buf()))?;
+
+    let certified_key = CertifiedKey::new(certificates, ring::sign::any_supported_type(&key)?);
+    certified_key.keys_match()?;
+
+    Ok(Arc::new(certified_key))
+}
+
+#[derive(Debug)]
+struct SniResolver {
+    default_certificate: Option<Arc<CertifiedKey>>,
+    certificates: HashMap<String, Arc<CertifiedKey>>
+}
+
+impl SniResolver {
+    fn find(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        let server_name = server_name.to_ascii_lowercase();
+        if let Some(certificate) = self.certificates.get(&server_name) {
+            return Some(Arc::clone(certificate));
+        }
+
+        let (_, parent) = server_name.split_once('.')?;
+        self.certificates.get(&format!("*.{parent}")).cloned()
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello.server_name()
+            .and_then(|server_name| self.find(server_name))
+            .or_else(|| self.default_certificate.clone())
+    }
+}
+
+pub struct TlsConfig {
+    default_certificate: Option<Arc<CertifiedKey>>,
+    certificates: HashMap<String, Arc<CertifiedKey>>,
+    alpn_protocols: Vec<Vec<u8>>
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig::new()
+    }
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        TlsConfig {
+            default_certificate: None,
+            certificates: HashMap::new(),
+            alpn_protocols: vec![b"http/1.1".to_vec()]
+        }
+    }
+
+    /// Certificate used when the client sends no SNI or an unknown server name.
+    pub fn default_certificate(mut self, cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Result<Self, TlsError> {
+        self.default_certificate = Some(load_certified_key(cert_path.as_ref(), key_path.as_ref())?);
+        Ok(self)
+    }
+
+    /// Certificate selected by SNI; `server_name` may be a wildcard such as `*.example.com`.
+    pub fn certificate(mut self, server_name: &str, cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Result<Self, TlsError> {
+        let certified_key = load_certified_key(cert_path.as_ref(), key_path.as_ref())?;
+        self.certificates.insert(server_name.to_ascii_lowercase(), certified_key);
+        Ok(self)
+    }
+
+    pub fn alpn_protocols(mut self, protocols: &[&str]) -> Self {
+        self.alpn_protocols = protocols.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+        self
+    }
+
+    pub fn build(self) -> Result<Arc<rustls::ServerConfig>, TlsError> {
+        if self.default_certificate.is_none() && self.certificates.is_empty() {
+            return Err(TlsError::NoCertificateConfigured);
+        }
+
+        let resolver = SniResolver {
+            default_certificate: self.default_certificate,
+            certificates: self.certificates
+        };
+
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = self.alpn_protocols;
+
+        Ok(Arc::new(config))
+    }
+}
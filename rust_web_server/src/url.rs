@@ -0,0 +1,121 @@
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None
+    }
+}
+
+// Strict decoding fails on malformed escapes; lenient decoding keeps them as they are.
+fn decode_bytes(input: &str, plus_as_space: bool, strict: bool) -> Option<Vec<u8>> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let escaped = bytes.get(index + 1..index + 3)
+                    .and_then(|hex| Some(hex_value(hex[0])? << 4 | hex_value(hex[1])?));
+                match escaped {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        index += 3;
+                        continue;
+                    },
+                    None if strict => return None,
+                    None => decoded.push(b'%')
+                }
+            },
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte)
+        }
+        index += 1;
+    }
+
+    Some(decoded)
+}
+
+/// Decodes `%XX` escapes, failing on malformed escapes or invalid UTF-8.
+pub fn percent_decode(input: &str) -> Option<String> {
+    String::from_utf8(decode_bytes(input, false, true)?).ok()
+}
+
+/// Decodes an `application/x-www-form-urlencoded` name or value, never failing.
+pub fn form_decode(input: &str) -> String {
+    let decoded = decode_bytes(input, true, false).unwrap_or_default();
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escapes everything except unreserved characters and, if `keep_slashes`, `/`.
+pub fn percent_encode(input: &str, keep_slashes: bool) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            b'/' if keep_slashes => encoded.push('/'),
+            byte => encoded.push_str(&format!("%{byte:02X}"))
+        }
+    }
+    encoded
+}
+
+/// Collapses repeated slashes and resolves `.` and `..` segments without leaving the root.
+pub fn normalize_path(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => {
+                segments.pop();
+            },
+            segment => segments.push(segment)
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    let ends_with_directory = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+    if ends_with_directory && !segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// Name/value pairs from a query string or urlencoded form, keeping repeated names in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query(Vec<(String, String)>);
+
+impl Query {
+    pub fn parse(input: &str) -> Self {
+        let pairs = input.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((name, value)) => (form_decode(name), form_decode(value)),
+                None => (form_decode(pair), String::new())
+            })
+            .collect();
+
+        Query(pairs)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0.iter().filter(move |(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
@@ -0,0 +1,125 @@
+use std::{mem, path::PathBuf, sync::Arc};
+use crate::{error_pages::{ErrorPage, ErrorPages}, file_cache::FileCache, http::{HttpResponse, Request}, middleware::{Middleware, MiddlewareChain}, router::Router, static_files};
+
+pub struct Site {
+    names: Vec<String>,
+    document_root: Option<PathBuf>,
+    file_cache: Option<Arc<FileCache>>,
+    router: Router,
+    error_pages: ErrorPages,
+    middleware: MiddlewareChain
+}
+
+impl Site {
+    pub fn new(router: Router) -> Self {
+        Site {
+            names: Vec::new(),
+            document_root: None,
+            file_cache: None,
+            router,
+            error_pages: ErrorPages::new(),
+            middleware: MiddlewareChain::new()
+        }
+    }
+
+    /// Adds a host name served by this site; `*.example.com` matches any subdomain of example.com.
+    pub fn name(mut self, name: &str) -> Self {
+        self.names.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Directory whose files are served for requests that match no route.
+    pub fn document_root(mut self, document_root: impl Into<PathBuf>) -> Self {
+        self.document_root = Some(document_root.into());
+        self
+    }
+
+    /// Serves the document root's files through `file_cache`, which may be shared with other sites.
+    pub fn file_cache(mut self, file_cache: Arc<FileCache>) -> Self {
+        self.file_cache = Some(file_cache);
+        self
+    }
+
+    /// Serves the HTML file at `path` for errors with `status`.
+    pub fn error_page(mut self, status: u16, path: impl Into<PathBuf>) -> Self {
+        self.error_pages = mem::take(&mut self.error_pages).page(status, ErrorPage::File(path.into()));
+        self
+    }
+
+    /// Replaces the site's error pages, including any added with `error_page`.
+    pub fn error_pages(mut self, error_pages: ErrorPages) -> Self {
+        self.error_pages = error_pages;
+        self
+    }
+
+    /// Wraps every request to this site, including static files and error pages.
+    pub fn middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    // Higher is a better match: exact names beat wildcards, longer wildcards beat shorter ones.
+    fn match_score(&self, host_name: &str) -> Option<usize> {
+        self.names.iter()
+            .filter_map(|name| match name.strip_prefix("*.") {
+                Some(domain) => host_name.strip_suffix(domain)
+                    .filter(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.'))
+                    .map(|_| domain.len()),
+                None => (name == host_name).then_some(usize::MAX)
+            })
+            .max()
+    }
+
+    pub fn handle(&self, request: &mut Request) -> HttpResponse {
+        self.middleware.run(request, &|request| self.handle_request(request))
+    }
+
+    fn handle_request(&self, request: &mut Request) -> HttpResponse {
+        let response = self.router.try_handle(request)
+            .or_else(|| self.document_root.as_ref().and_then(|root| match &self.file_cache {
+                Some(file_cache) => file_cache.serve(root, request),
+                None => static_files::serve_file(root, request)
+            }))
+            .unwrap_or_else(|| self.router.handle_fallback(request));
+
+        self.error_pages.apply(request, response)
+    }
+}
+
+pub struct VirtualHosts {
+    sites: Vec<Site>,
+    default_site: Site
+}
+
+impl VirtualHosts {
+    /// `default_site` answers requests whose `Host` matches no other site.
+    pub fn new(default_site: Site) -> Self {
+        VirtualHosts { sites: Vec::new(), default_site }
+    }
+
+    pub fn site(mut self, site: Site) -> Self {
+        self.sites.push(site);
+        self
+    }
+
+    pub fn find_site(&self, host_name: Option<&str>) -> &Site {
+        host_name
+            .and_then(|host_name| {
+                self.sites.iter()
+                    .filter_map(|site| site.match_score(host_name).map(|score| (score, site)))
+                    .max_by_key(|(score, _)| *score)
+            })
+            .map_or(&self.default_site, |(_, site)| site)
+    }
+
+    pub fn handle(&self, request: &mut Request) -> HttpResponse {
+        let host_name = request.host_name();
+
+        // RFC 9112 requires a Host header on every HTTP/1.1 request.
+        if host_name.is_none() && request.version == "HTTP/1.1" {
+            return self.default_site.error_pages.apply(request, HttpResponse::bad_request());
+        }
+
+        self.find_site(host_name.as_deref()).handle(request)
+    }
+}
@@ -0,0 +1,395 @@
+use std::{io, sync::Arc, time::Duration};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+use crate::http::{has_token, HttpResponse, Request, Stream};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+const DEFAULT_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+pub mod close_code {
+    pub const NORMAL: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const UNSUPPORTED_DATA: u16 = 1003;
+    pub const INVALID_PAYLOAD: u16 = 1007;
+    pub const POLICY_VIOLATION: u16 = 1008;
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+    pub const INTERNAL_ERROR: u16 = 1011;
+}
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    Io(io::Error),
+    Protocol(&'static str),
+    InvalidUtf8,
+    MessageTooBig,
+    Closed
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(error: io::Error) -> Self {
+        WebSocketError::Io(error)
+    }
+}
+
+impl WebSocketError {
+    fn close_code(&self) -> Option<u16> {
+        match self {
+            WebSocketError::Protocol(_) => Some(close_code::PROTOCOL_ERROR),
+            WebSocketError::InvalidUtf8 => Some(close_code::INVALID_PAYLOAD),
+            WebSocketError::MessageTooBig => Some(close_code::MESSAGE_TOO_BIG),
+            WebSocketError::Io(_) | WebSocketError::Closed => None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>)
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>
+}
+
+pub struct WebSocket<'a> {
+    stream: &'a mut dyn Stream,
+    fragments: Option<(u8, Vec<u8>)>,
+    max_message_size: usize,
+    close_timeout: Duration,
+    close_sent: bool,
+    close_received: bool
+}
+
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999)
+}
+
+impl<'a> WebSocket<'a> {
+    pub fn new(stream: &'a mut dyn Stream) -> Self {
+        WebSocket {
+            stream,
+            fragments: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            close_timeout: DEFAULT_CLOSE_TIMEOUT,
+            close_sent: false,
+            close_received: false
+        }
+    }
+
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// How long `close` waits for the peer to answer before giving up on it.
+    pub fn set_close_timeout(&mut self, close_timeout: Duration) {
+        self.close_timeout = close_timeout;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.close_sent || self.close_received
+    }
+
+    /// Waits for the next complete message. Pings are answered automatically
+    /// and a received close is echoed before `Message::Close` is returned.
+    pub fn recv(&mut self) -> Result<Message, WebSocketError> {
+        if self.close_received {
+            return Err(WebSocketError::Closed);
+        }
+
+        let result = self.read_message();
+        if let Some(code) = result.as_ref().err().and_then(WebSocketError::close_code) {
+            let _ = self.send_close(code, "");
+            self.close_received = true;
+        }
+
+        result
+    }
+
+    pub fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        match message {
+            Message::Text(text) => self.send_text(&text),
+            Message::Binary(data) => self.send_binary(&data),
+            Message::Ping(data) => self.send_control(OP_PING, &data),
+            Message::Pong(data) => self.send_control(OP_PONG, &data),
+            Message::Close(Some(frame)) => self.close(frame.code, &frame.reason),
+            Message::Close(None) => self.close(close_code::NORMAL, "")
+        }
+    }
+
+    pub fn send_text(&mut self, text: &str) -> Result<(), WebSocketError> {
+        self.write_data(OP_TEXT, text.as_bytes())
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> Result<(), WebSocketError> {
+        self.write_data(OP_BINARY, data)
+    }
+
+    /// Sends a text or binary message split into frames of at most `fragment_size` bytes.
+    pub fn send_fragmented(&mut self, message: Message, fragment_size: usize) -> Result<(), WebSocketError> {
+        let (opcode, data) = match &message {
+            Message::Text(text) => (OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => (OP_BINARY, data.as_slice()),
+            _ => return self.send(message)
+        };
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+
+        let mut chunks = data.chunks(fragment_size.max(1)).peekable();
+        let mut frame_opcode = opcode;
+        if chunks.peek().is_none() {
+            return self.write_frame(true, opcode, &[]);
+        }
+
+        while let Some(chunk) = chunks.next() {
+            self.write_frame(chunks.peek().is_none(), frame_opcode, chunk)?;
+            frame_opcode = OP_CONTINUATION;
+        }
+
+        Ok(())
+    }
+
+    pub fn ping(&mut self, data: &[u8]) -> Result<(), WebSocketError> {
+        self.send_control(OP_PING, data)
+    }
+
+    /// Starts the closing handshake and waits for the peer to answer it, up to the close timeout.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        self.send_close(code, reason)?;
+        if !self.close_received {
+            self.stream.set_read_timeout(Some(self.close_timeout))?;
+        }
+
+        while !self.close_received {
+            match self.read_message() {
+                Ok(Message::Close(_)) => break,
+                Ok(_) => continue,
+                Err(WebSocketError::Io(error)) if matches!(error.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+                Err(error) => return Err(error)
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Ok(());
+        }
+
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        payload.truncate(125);
+
+        self.write_frame(true, OP_CLOSE, &payload)?;
+        self.close_sent = true;
+        Ok(())
+    }
+
+    fn send_control(&mut self, opcode: u8, data: &[u8]) -> Result<(), WebSocketError> {
+        if data.len() > 125 {
+            return Err(WebSocketError::Protocol("control frame payload too long"));
+        }
+
+        self.write_data(opcode, data)
+    }
+
+    fn write_data(&mut self, opcode: u8, data: &[u8]) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+
+        self.write_frame(true, opcode, data)
+    }
+
+    fn write_frame(&mut self, fin: bool, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        let mut head = vec![if fin { 0x80 | opcode } else { opcode }];
+        match payload.len() {
+            length @ 0..=125 => head.push(length as u8),
+            length @ 126..=0xFFFF => {
+                head.push(126);
+                head.extend_from_slice(&(length as u16).to_be_bytes());
+            },
+            length => {
+                head.push(127);
+                head.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+
+        self.stream.write_all(&head)?;
+        self.stream.write_all(payload)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> Result<Frame, WebSocketError> {
+        let mut head = [0u8; 2];
+        self.stream.read_exact(&mut head)?;
+
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        if head[0] & 0x70 != 0 {
+            return Err(WebSocketError::Protocol("reserved bits set"));
+        }
+        if head[1] & 0x80 == 0 {
+            return Err(WebSocketError::Protocol("client frames must be masked"));
+        }
+
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut length = [0u8; 2];
+                self.stream.read_exact(&mut length)?;
+                u16::from_be_bytes(length) as u64
+            },
+            127 => {
+                let mut length = [0u8; 8];
+                self.stream.read_exact(&mut length)?;
+                u64::from_be_bytes(length)
+            },
+            length => length as u64
+        };
+
+        if opcode >= OP_CLOSE && (!fin || length > 125) {
+            return Err(WebSocketError::Protocol("invalid control frame"));
+        }
+        if length > self.max_message_size as u64 {
+            return Err(WebSocketError::MessageTooBig);
+        }
+
+        let mut mask = [0u8; 4];
+        self.stream.read_exact(&mut mask)?;
+
+        let mut payload = vec![0u8; length as usize];
+        self.stream.read_exact(&mut payload)?;
+        for (index, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+
+        Ok(Frame { fin, opcode, payload })
+    }
+
+    fn read_message(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            let frame = self.read_frame()?;
+
+            match frame.opcode {
+                OP_PING => {
+                    if !self.close_sent {
+                        self.write_frame(true, OP_PONG, &frame.payload)?;
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                },
+                OP_PONG => return Ok(Message::Pong(frame.payload)),
+                OP_CLOSE => return self.receive_close(&frame.payload),
+                OP_TEXT | OP_BINARY if self.fragments.is_none() => {
+                    self.fragments = Some((frame.opcode, frame.payload));
+                },
+                OP_CONTINUATION => {
+                    let max_message_size = self.max_message_size;
+                    let Some((_, data)) = self.fragments.as_mut() else {
+                        return Err(WebSocketError::Protocol("unexpected continuation frame"));
+                    };
+                    if data.len() + frame.payload.len() > max_message_size {
+                        return Err(WebSocketError::MessageTooBig);
+                    }
+                    data.extend_from_slice(&frame.payload);
+                },
+                OP_TEXT | OP_BINARY => return Err(WebSocketError::Protocol("expected continuation frame")),
+                _ => return Err(WebSocketError::Protocol("unknown opcode"))
+            }
+
+            if frame.fin {
+                if let Some((opcode, data)) = self.fragments.take() {
+                    return match opcode {
+                        OP_TEXT => String::from_utf8(data).map(Message::Text).map_err(|_| WebSocketError::InvalidUtf8),
+                        _ => Ok(Message::Binary(data))
+                    };
+                }
+            }
+        }
+    }
+
+    fn receive_close(&mut self, payload: &[u8]) -> Result<Message, WebSocketError> {
+        let close_frame = match payload {
+            [] => None,
+            [_] => return Err(WebSocketError::Protocol("invalid close payload")),
+            [high, low, reason @ ..] => {
+                let code = u16::from_be_bytes([*high, *low]);
+                if !is_valid_close_code(code) {
+                    return Err(WebSocketError::Protocol("invalid close code"));
+                }
+
+                let reason = std::str::from_utf8(reason).map_err(|_| WebSocketError::InvalidUtf8)?;
+                Some(CloseFrame { code, reason: reason.into() })
+            }
+        };
+
+        self.close_received = true;
+        let code = close_frame.as_ref().map_or(close_code::NORMAL, |frame| frame.code);
+        self.send_close(code, "")?;
+
+        Ok(Message::Close(close_frame))
+    }
+}
+
+pub fn get_accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(GUID.as_bytes());
+
+    STANDARD.encode(hasher.finalize())
+}
+
+/// Validates the opening handshake and answers it with `101 Switching Protocols`,
+/// handing the connection to `handler` afterwards.
+pub fn upgrade<F>(request: &Request, handler: Arc<F>) -> HttpResponse where F: Fn(&mut WebSocket) + Send + Sync + 'static {
+    if !has_token(request.header("Upgrade"), "websocket") {
+        return HttpResponse::error(426)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade");
+    }
+
+    if request.method != "GET" || !has_token(request.header("Connection"), "upgrade") {
+        return HttpResponse::bad_request();
+    }
+
+    if request.header("Sec-WebSocket-Version") != Some("13") {
+        return HttpResponse::error(426).with_header("Sec-WebSocket-Version", "13");
+    }
+
+    let key = match request.header("Sec-WebSocket-Key") {
+        Some(key) if STANDARD.decode(key).is_ok_and(|decoded| decoded.len() == 16) => key,
+        _ => return HttpResponse::bad_request()
+    };
+
+    HttpResponse::switching_protocols("websocket", Box::new(move |stream| {
+        let mut websocket = WebSocket::new(stream);
+        handler(&mut websocket);
+
+        if !websocket.is_closed() {
+            let _ = websocket.close(close_code::NORMAL, "");
+        }
+    }))
+    .with_header("Sec-WebSocket-Accept", &get_accept_key(key))
+}
@@ -0,0 +1,1365 @@
+use std::{fs::{self, File}, io::{BufRead, BufReader, Read, Write}, net::{IpAddr, SocketAddr, TcpListener, TcpStream}, os::unix::{fs::PermissionsExt, net::UnixStream}, sync::{mpsc, Arc}, thread, time::{Duration, SystemTime}};
+use rust_web_server::{
+    access::{Access, AccessControl, Cidr},
+    assets::Assets,
+    auth::{BasicAuth, Htpasswd},
+    cgi::Cgi,
+    chunked::ChunkedReader,
+    cookie::{Cookie, CookieSigner},
+    cors::Cors,
+    error_pages::{ErrorPage, ErrorPages},
+    fastcgi::FastCgi,
+    file_cache::FileCache,
+    form::{read_form, read_limited},
+    health::Health,
+    http::{read_request, read_response_head, Body, HttpResponse, Request},
+    json::read_json,
+    listener::Listener,
+    metrics::Metrics,
+    multipart::Multipart,
+    proxy::{Balancing, UpstreamPool},
+    ratelimit::{ConnectionLimits, RateLimiter},
+    reactor::Reactor,
+    router::Router,
+    server::{Server, ServerHandle},
+    session::{FileStore, MemoryStore, SessionStore, Sessions},
+    sse::Event,
+    template::{Context, Templates},
+    testing::TestClient,
+    vhost::{Site, VirtualHosts},
+    websocket::Message,
+    ThreadPool
+};
+use serde::{Deserialize, Serialize};
+
+fn start(router: Router) -> (ServerHandle, TestClient) {
+    let thread_pool = ThreadPool::new(4).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let server = Server::new(thread_pool, move |request| router.handle(request));
+    let handle = server.start("127.0.0.1:0").unwrap();
+    let client = TestClient::new(handle.local_addr());
+    (handle, client)
+}
+
+#[test]
+fn routes_unknown_paths_and_methods() {
+    let (_server, client) = start(Router::new().get("/", |_| HttpResponse::ok("home")));
+
+    let response = client.get("/").unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "home");
+
+    assert_eq!(client.get("/missing").unwrap().status, 404);
+
+    let response = client.request("DELETE", "/").send().unwrap();
+    assert_eq!(response.status, 405);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD"));
+
+    // HEAD runs the GET route and gets its head without the body.
+    let response = client.request("HEAD", "/").send().unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Length"), Some("4"));
+    assert_eq!(response.text(), "");
+}
+
+#[test]
+fn rejects_malformed_requests() {
+    let (_server, client) = start(Router::new());
+
+    assert_eq!(client.send_raw(b"NONSENSE\r\n\r\n").unwrap().status, 400);
+    assert_eq!(client.send_raw(b"GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n").unwrap().status, 400);
+
+    // Heads stop at 64 KiB, whether in one long header or many short ones.
+    let request_line = "GET / HTTP/1.1\r\n";
+    let long_header = format!("{request_line}X-Long: {}", "a".repeat(64 * 1024 - request_line.len() - 8));
+    let many_headers = format!("{request_line}{}", "X: y\r\n".repeat((64 * 1024 - request_line.len()) / 6));
+    for head in [long_header, many_headers] {
+        let response = client.send_raw(head.as_bytes()).unwrap();
+        assert_eq!((response.status, response.header("Connection")), (431, Some("close")));
+    }
+}
+
+#[test]
+fn decodes_paths_query_strings_and_forms() {
+    let router = Router::new()
+        .get("/a b/*", |request| HttpResponse::ok(format!("{} {:?}", request.path(), request.query().get_all("q").collect::<Vec<_>>())))
+        .post("/form", |request| match read_form(request, 1024) {
+            Ok(form) => HttpResponse::ok(format!("{} {}", form.get("name").unwrap_or_default(), form.get("note").unwrap_or_default())),
+            Err(error) => error.get_response()
+        });
+    let (_server, client) = start(router);
+
+    assert_eq!(client.get("/a%20b/./c/../d?q=1+2&q=%C3%A9").unwrap().text(), "/a b/d [\"1 2\", \"é\"]");
+
+    let response = client.request("POST", "/form").form(&[("name", "Ann"), ("note", "a&b=c")]).send().unwrap();
+    assert_eq!(response.text(), "Ann a&b=c");
+
+    let response = client.request("POST", "/form").header("Content-Type", "text/plain").body("name=x").send().unwrap();
+    assert_eq!(response.status, 415);
+}
+
+#[test]
+fn reads_multipart_uploads() {
+    let router = Router::new().post("/upload", |request| {
+        let mut multipart = match Multipart::new(request) {
+            Ok(multipart) => multipart,
+            Err(error) => return error.get_response()
+        };
+
+        let mut fields = Vec::new();
+        while let Ok(Some(mut part)) = multipart.next_part() {
+            let (name, file_name) = (part.name().unwrap_or_default(), part.file_name());
+            fields.push(format!("{name}:{file_name:?}:{}", part.read_to_string().unwrap()));
+        }
+        HttpResponse::ok(fields.join(","))
+    });
+    let (_server, client) = start(router);
+
+    let body = "--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n\
+        --XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\nContent-Type: text/plain\r\n\r\nline\r\n--X\r\n\
+        --XyZ--\r\n";
+    let response = client.request("POST", "/upload")
+        .header("Content-Type", "multipart/form-data; boundary=XyZ")
+        .body(body)
+        .send()
+        .unwrap();
+    assert_eq!(response.text(), "title:None:hello,file:Some(\"a.txt\"):line\r\n--X");
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Item {
+    name: String,
+    count: u32
+}
+
+#[test]
+fn extracts_and_returns_json() {
+    let router = Router::new().post("/items", |request| match read_json::<Item>(request, 1024) {
+        Ok(item) => HttpResponse::json(201, &item),
+        Err(error) => error.get_response()
+    });
+    let (_server, client) = start(router);
+
+    let item = Item { name: "pen".into(), count: 2 };
+    let response = client.request("POST", "/items").json(&item).send().unwrap();
+    assert_eq!(response.status, 201);
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    assert_eq!(response.json::<Item>().unwrap(), item);
+
+    let response = client.request("POST", "/items").header("Content-Type", "application/json").body("{").send().unwrap();
+    assert_eq!(response.status, 400);
+    assert_eq!(response.json::<serde_json::Value>().unwrap()["error"]["status"], 400);
+
+    assert_eq!(client.request("POST", "/items").body("{}").send().unwrap().status, 415);
+}
+
+#[test]
+fn keeps_sessions_across_requests() {
+    let sessions = Arc::new(Sessions::new(Arc::new(MemoryStore::new())));
+    let router = Router::new().get("/count", move |request| {
+        let mut session = sessions.load(request);
+        let count = session.get("count").and_then(|count| count.parse::<u32>().ok()).unwrap_or(0) + 1;
+        session.insert("count", &count.to_string());
+        sessions.save(session, HttpResponse::ok(count.to_string()))
+    });
+    let (_server, client) = start(router);
+
+    let first = client.get("/count").unwrap();
+    assert_eq!(first.text(), "1");
+    let set_cookie = first.header("Set-Cookie").unwrap();
+    assert!(set_cookie.contains("HttpOnly") && set_cookie.contains("SameSite=Lax"));
+
+    let cookie = set_cookie.split(';').next().unwrap();
+    assert_eq!(client.request("GET", "/count").header("Cookie", cookie).send().unwrap().text(), "2");
+    assert_eq!(client.get("/count").unwrap().text(), "1");
+}
+
+#[test]
+fn answers_cors_preflight_requests() {
+    let router = Router::new()
+        .post("/api", |_| HttpResponse::ok("posted"))
+        .cors(Cors::new().allow_origin("https://*.example.com").allow_methods(&["POST"]).allow_headers(&["Content-Type"]));
+    let (_server, client) = start(router);
+
+    let response = client.request("OPTIONS", "/api")
+        .header("Origin", "https://app.example.com")
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .unwrap();
+    assert_eq!(response.status, 204);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("https://app.example.com"));
+    assert_eq!(response.header("Access-Control-Allow-Methods"), Some("POST"));
+
+    let response = client.request("POST", "/api").header("Origin", "https://evil.test").send().unwrap();
+    assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+    assert_eq!(response.header("Vary"), Some("Origin"));
+
+    // Credentials are never allowed for an origin that only `*` lets in.
+    let (_server, client) = start(Router::new()
+        .get("/open", |_| HttpResponse::ok("open"))
+        .middleware(Cors::new().allow_origin("*").allow_credentials(true)));
+    let response = client.request("GET", "/open").header("Origin", "https://evil.test").send().unwrap();
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(response.header("Access-Control-Allow-Credentials"), None);
+    assert_eq!(response.header("Vary"), None);
+
+    let (_server, client) = start(Router::new()
+        .get("/open", |_| HttpResponse::ok("open"))
+        .middleware(Cors::new().allow_origin("https://app.example.com").allow_origin("*").allow_credentials(true)));
+    let response = client.request("GET", "/open").header("Origin", "https://app.example.com").send().unwrap();
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("https://app.example.com"));
+    assert_eq!(response.header("Access-Control-Allow-Credentials"), Some("true"));
+    let response = client.request("GET", "/open").header("Origin", "https://evil.test").send().unwrap();
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(response.header("Access-Control-Allow-Credentials"), None);
+    assert_eq!(response.header("Vary"), Some("Origin"));
+}
+
+#[test]
+fn requires_basic_credentials() {
+    let hash = bcrypt::hash("secret", 4).unwrap();
+    let htpasswd = Arc::new(Htpasswd::parse(&format!("ann:{hash}")).unwrap());
+    let router = Router::new()
+        .get("/admin", |_| HttpResponse::ok("welcome"))
+        .middleware(BasicAuth::new("Admin", htpasswd));
+    let (_server, client) = start(router);
+
+    let response = client.get("/admin").unwrap();
+    assert_eq!(response.status, 401);
+    assert_eq!(response.header("WWW-Authenticate"), Some("Basic realm=\"Admin\", charset=\"UTF-8\""));
+
+    // "ann:secret" and "ann:wrong"
+    assert_eq!(client.request("GET", "/admin").header("Authorization", "Basic YW5uOnNlY3JldA==").send().unwrap().status, 200);
+    assert_eq!(client.request("GET", "/admin").header("Authorization", "Basic YW5uOndyb25n").send().unwrap().status, 401);
+    // "bob:secret", a user the file does not have
+    assert_eq!(client.request("GET", "/admin").header("Authorization", "Basic Ym9iOnNlY3JldA==").send().unwrap().status, 401);
+}
+
+#[test]
+fn allows_and_denies_address_ranges() {
+    let ip = |address: &str| Some(address.parse::<IpAddr>().unwrap());
+    let access = AccessControl::new()
+        .allow("10.1.0.0/16").unwrap()
+        .deny("10.0.0.0/8").unwrap()
+        .allow("2001:db8::/32").unwrap()
+        .default_access(Access::Deny);
+
+    // The first matching range decides.
+    assert_eq!(access.check(ip("10.1.2.3")), Access::Allow);
+    assert_eq!(access.check(ip("10.2.0.1")), Access::Deny);
+    assert_eq!(access.check(ip("2001:db8:1::1")), Access::Allow);
+    assert_eq!(access.check(ip("2001:db9::1")), Access::Deny);
+    // IPv4 clients on a dual-stack socket arrive as mapped IPv6 addresses.
+    assert_eq!(access.check(ip("::ffff:10.1.2.3")), Access::Allow);
+    assert_eq!(access.check(ip("192.168.0.1")), Access::Deny);
+    assert_eq!(access.check(None), Access::Deny);
+
+    assert_eq!(Cidr::parse("10.0.0.1").unwrap(), Cidr::parse("10.0.0.1/32").unwrap());
+    assert!(Cidr::parse("0.0.0.0/0").unwrap().contains("203.0.113.9".parse().unwrap()));
+    assert!(Cidr::parse("10.0.0.0/33").is_err());
+    assert!(Cidr::parse("not an address").is_err());
+
+    let (_server, client) = start(Router::new()
+        .get("/", |_| HttpResponse::ok("open"))
+        .middleware(AccessControl::new().deny("127.0.0.0/8").unwrap()));
+    assert_eq!(client.get("/").unwrap().status, 403);
+    let (_server, client) = start(Router::new()
+        .get("/", |_| HttpResponse::ok("open"))
+        .middleware(AccessControl::new().allow("127.0.0.1").unwrap().deny("127.0.0.0/8").unwrap()));
+    assert_eq!(client.get("/").unwrap().text(), "open");
+}
+
+#[test]
+fn throttles_clients_over_the_rate_limit() {
+    let limiter = RateLimiter::new(0.5, 2);
+    let stats = limiter.stats();
+    let router = Router::new().get("/", |_| HttpResponse::ok("ok")).middleware(limiter);
+    let (_server, client) = start(router);
+
+    assert_eq!(client.get("/").unwrap().status, 200);
+    assert_eq!(client.get("/").unwrap().status, 200);
+    let response = client.get("/").unwrap();
+    assert_eq!(response.status, 429);
+    assert_eq!(response.header("Retry-After"), Some("2"));
+    assert_eq!((stats.allowed(), stats.throttled()), (2, 1));
+
+    // Past the tracked key limit, with every bucket still in use, new keys make room for themselves.
+    let limiter = RateLimiter::new(0.001, 1);
+    for index in 0..100_000 {
+        assert_eq!(limiter.check(&index.to_string()), Ok(()));
+    }
+    assert_eq!(limiter.check("99999"), Err(1000));
+    for index in 100_000..100_100 {
+        assert_eq!(limiter.check(&index.to_string()), Ok(()));
+    }
+}
+
+#[test]
+fn limits_open_connections() {
+    let localhost = Some(IpAddr::from([127, 0, 0, 1]));
+    let limits = Arc::new(ConnectionLimits::new(3, 2));
+    let first = limits.acquire(localhost).unwrap();
+    // IPv4-mapped addresses count against the same client.
+    let second = limits.acquire(Some("::ffff:127.0.0.1".parse().unwrap())).unwrap();
+    assert!(limits.acquire(localhost).is_none());
+    let other = limits.acquire(Some(IpAddr::from([10, 0, 0, 1]))).unwrap();
+    assert!(limits.acquire(Some(IpAddr::from([10, 0, 0, 2]))).is_none());
+    assert_eq!((limits.active(), limits.rejected()), (3, 2));
+
+    drop(first);
+    drop(other);
+    assert_eq!(limits.active(), 1);
+    let third = limits.acquire(localhost).unwrap();
+    drop((second, third));
+    assert_eq!(limits.active(), 0);
+
+    let limits = Arc::new(ConnectionLimits::new(10, 1));
+    let router = Router::new().get("/", |_| HttpResponse::ok("ok"));
+    let thread_pool = ThreadPool::new(2).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let handle = Server::new(thread_pool, move |request| router.handle(request))
+        .with_connection_limits(Arc::clone(&limits))
+        .start("127.0.0.1:0")
+        .unwrap();
+    let client = TestClient::new(handle.local_addr());
+
+    // A second connection from the same client is turned away while the first stays open.
+    let mut open = client.connect().unwrap();
+    open.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(open.try_clone().unwrap());
+    assert_eq!(read_response_head(&mut reader).unwrap().0, 200);
+    let response = client.get("/").unwrap();
+    assert_eq!(response.status, 429);
+    assert_eq!(response.header("Retry-After"), Some("1"));
+    assert_eq!(limits.rejected(), 1);
+
+    drop((open, reader));
+    while limits.active() > 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(client.get("/").unwrap().status, 200);
+}
+
+#[test]
+fn serves_embedded_assets_with_etags() {
+    let router = Router::new().fallback(|request: &mut Request| {
+        Assets::Embedded.serve(request).unwrap_or_else(|| HttpResponse::not_found("Not Found"))
+    });
+    let (_server, client) = start(router);
+
+    let response = client.get("/layout.html").unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("text/html; charset=utf-8"));
+    let etag = response.header("ETag").unwrap();
+
+    let response = client.request("GET", "/layout.html").header("If-None-Match", etag).send().unwrap();
+    assert_eq!(response.status, 304);
+    assert!(response.body.is_empty());
+
+    assert_eq!(client.get("/../Cargo.toml").unwrap().status, 404);
+}
+
+#[test]
+fn stops_accepting_after_shutdown() {
+    let (server, client) = start(Router::new().get("/", |_| HttpResponse::ok("ok")));
+    let client = client.timeout(Duration::from_secs(1));
+    assert_eq!(client.get("/").unwrap().status, 200);
+
+    server.shutdown();
+    assert!(client.get("/").is_err());
+}
+
+#[test]
+fn reports_metrics_and_readiness() {
+    let thread_pool = ThreadPool::new(2).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let metrics = Arc::new(Metrics::new().thread_pool(thread_pool.stats()));
+    let health = Arc::new(Health::new());
+    let (metrics_route, readiness) = (Arc::clone(&metrics), Arc::clone(&health));
+    let router = Router::new()
+        .get("/items/*", |_| HttpResponse::ok("item"))
+        .get("/metrics", move |_| metrics_route.get_response())
+        .get("/readyz", move |_| readiness.get_readiness_response());
+    let server = Server::new(thread_pool, move |request| router.handle(request))
+        .with_metrics(Arc::clone(&metrics))
+        .with_health(Arc::clone(&health))
+        .start("127.0.0.1:0")
+        .unwrap();
+    let client = TestClient::new(server.local_addr());
+
+    client.get("/items/1").unwrap();
+    client.get("/items/2").unwrap();
+    client.get("/missing").unwrap();
+    assert_eq!(client.get("/readyz").unwrap().status, 200);
+
+    let response = client.get("/metrics").unwrap();
+    assert_eq!(response.header("Content-Type"), Some("text/plain; version=0.0.4; charset=utf-8"));
+    let text = response.text();
+    assert!(text.contains("http_requests_total{method=\"GET\",route=\"/items/*\",status=\"200\"} 2"));
+    assert!(text.contains("http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"} 1"));
+    assert!(text.contains("http_request_duration_seconds_count{route=\"/items/*\"} 2"));
+    assert!(text.contains("http_open_connections 1"));
+    assert!(text.contains("thread_pool_workers 2"));
+    assert!(text.contains("thread_pool_busy_workers 1"));
+    assert!(!text.contains("http_received_bytes_total 0\n"));
+
+    server.shutdown();
+    assert!(!health.is_ready());
+    assert_eq!(metrics.open_connections(), 0);
+}
+
+#[test]
+fn renders_templates() {
+    let directory = std::env::temp_dir().join(format!("templates-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let files = [
+        ("escape.html", "{{ name }} {{ name | safe }} {{ name|safe }} {{name |safe}}"),
+        ("if.html", "{% if not admin %}guest{% else %}admin{% endif %}"),
+        ("for.html", "{% for item in items %}{{ loop.index }}:{{ item }}{% if loop.first %}(first){% endif %}{% if loop.last %}(last){% endif %} {% endfor %}"),
+        ("include.html", "[{% include \"partial.html\" %}]"),
+        ("partial.html", "{{ name }}"),
+        ("base.html", "{% block title %}Base{% endblock %}|{% if show %}{% block body %}base body{% endblock %}{% endif %}|{% for item in items %}{% block item %}{{ item }}{% endblock %}{% endfor %}"),
+        ("middle.html", "{% extends \"base.html\" %}{% block body %}middle {% block detail %}middle detail{% endblock %}{% endblock %}"),
+        ("page.html", "{% extends \"middle.html\" %}{% block title %}Page{% endblock %}{% block detail %}page detail {% block item %}<{{ item }}>{% endblock %}{% endblock %}"),
+        ("changing.html", "before")
+    ];
+    for (name, source) in files {
+        fs::write(directory.join(name), source).unwrap();
+    }
+    let templates = Templates::new(&directory);
+    let context = || Context::new().insert("name", "<b>'&'</b>").insert("items", vec!["a", "b", "c"]).insert("show", true);
+
+    assert_eq!(templates.render("escape.html", context()).unwrap(), "&lt;b&gt;&#39;&amp;&#39;&lt;/b&gt; <b>'&'</b> <b>'&'</b> <b>'&'</b>");
+    assert_eq!(templates.render("if.html", context()).unwrap(), "guest");
+    assert_eq!(templates.render("if.html", context().insert("admin", true)).unwrap(), "admin");
+    assert_eq!(templates.render("for.html", context()).unwrap(), "1:a(first) 2:b 3:c(last) ");
+    assert_eq!(templates.render("include.html", context()).unwrap(), "[&lt;b&gt;&#39;&amp;&#39;&lt;/b&gt;]");
+
+    // The most derived template wins, including for blocks nested in other blocks, `if` and `for`.
+    assert_eq!(templates.render("page.html", context()).unwrap(), "Page|middle page detail <>|<a><b><c>");
+    assert_eq!(templates.render("page.html", context().insert("show", false)).unwrap(), "Page||<a><b><c>");
+
+    // A template whose file changes is compiled again.
+    assert_eq!(templates.render("changing.html", context()).unwrap(), "before");
+    fs::write(directory.join("changing.html"), "after").unwrap();
+    File::options().write(true).open(directory.join("changing.html")).unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+    assert_eq!(templates.render("changing.html", context()).unwrap(), "after");
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+// Writes a self-signed certificate for `server_name` and its key, returning their paths and the DER certificate.
+fn write_certificate(directory: &std::path::Path, server_name: &str) -> (std::path::PathBuf, std::path::PathBuf, Vec<u8>) {
+    let certified = rcgen::generate_simple_self_signed(vec![server_name.to_string()]).unwrap();
+    let file_name = server_name.replace('*', "wildcard");
+    let (cert_path, key_path) = (directory.join(format!("{file_name}.crt")), directory.join(format!("{file_name}.key")));
+    fs::write(&cert_path, certified.cert.pem()).unwrap();
+    fs::write(&key_path, certified.signing_key.serialize_pem()).unwrap();
+    (cert_path, key_path, certified.cert.der().to_vec())
+}
+
+#[test]
+fn selects_tls_certificates_by_server_name() {
+    let directory = std::env::temp_dir().join(format!("tls-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let (default_cert, default_key, default_der) = write_certificate(&directory, "localhost");
+    let (named_cert, named_key, named_der) = write_certificate(&directory, "example.test");
+    let (wildcard_cert, wildcard_key, wildcard_der) = write_certificate(&directory, "*.wild.test");
+
+    let tls_config = rust_web_server::tls::TlsConfig::new()
+        .default_certificate(&default_cert, &default_key).unwrap()
+        .certificate("example.test", &named_cert, &named_key).unwrap()
+        .certificate("*.wild.test", &wildcard_cert, &wildcard_key).unwrap()
+        .build().unwrap();
+
+    let router = Router::new().get("/", |request| HttpResponse::ok(format!("secure: {}", request.secure)));
+    let thread_pool = ThreadPool::new(2).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let server = Arc::new(Server::new(thread_pool, move |request| router.handle(request)).with_tls(tls_config));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            server.handle_tls_connection(stream);
+        }
+    });
+
+    let mut roots = rustls::RootCertStore::empty();
+    for der in [&default_der, &named_der, &wildcard_der] {
+        roots.add(rustls::pki_types::CertificateDer::from(der.clone())).unwrap();
+    }
+    let mut client_config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions().unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    let client_config = Arc::new(client_config);
+
+    for (server_name, expected) in [("example.test", &named_der), ("api.wild.test", &wildcard_der), ("localhost", &default_der)] {
+        let connection = rustls::ClientConnection::new(Arc::clone(&client_config), server_name.to_string().try_into().unwrap()).unwrap();
+        let mut stream = rustls::StreamOwned::new(connection, TcpStream::connect(addr).unwrap());
+        stream.write_all(format!("GET / HTTP/1.1\r\nHost: {server_name}\r\nConnection: close\r\n\r\n").as_bytes()).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+
+        assert_eq!(stream.conn.peer_certificates().unwrap()[0].as_ref(), expected.as_slice(), "{server_name}");
+        assert_eq!(stream.conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nsecure: true"));
+    }
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn redirects_plaintext_requests_to_https() {
+    let thread_pool = ThreadPool::new(2).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let server = Server::new(thread_pool, |_| HttpResponse::ok("plaintext")).redirect_to_https(8443);
+    let handle = server.start("127.0.0.1:0").unwrap();
+    let client = TestClient::new(handle.local_addr());
+
+    let response = client.request("GET", "/path?x=1").header("Host", "example.test:8080").send().unwrap();
+    assert_eq!(response.status, 301);
+    assert_eq!(response.header("Location"), Some("https://example.test:8443/path?x=1"));
+
+    let response = client.request("POST", "/form").header("Host", "example.test").body("a=1").send().unwrap();
+    assert_eq!(response.status, 308);
+    assert_eq!(response.header("Location"), Some("https://example.test:8443/form"));
+}
+
+fn write_masked_frame(stream: &mut TcpStream, first_byte: u8, payload: &[u8]) {
+    let mask = [0x37, 0xfa, 0x21, 0x3d];
+    let mut frame = vec![first_byte, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]));
+    stream.write_all(&frame).unwrap();
+}
+
+// Returns the first byte of a short server frame and its payload, which servers never mask.
+fn read_frame(reader: &mut impl Read) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head).unwrap();
+    assert_eq!(head[1] & 0x80, 0);
+    let mut payload = vec![0; (head[1] & 0x7f) as usize];
+    reader.read_exact(&mut payload).unwrap();
+    (head[0], payload)
+}
+
+fn open_websocket(client: &TestClient, path: &str) -> (TcpStream, BufReader<TcpStream>) {
+    let mut stream = client.connect().unwrap();
+    stream.write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+        Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n").as_bytes()).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let (status, headers) = read_response_head(&mut reader).unwrap();
+    assert_eq!(status, 101);
+    assert_eq!(headers.get("Sec-WebSocket-Accept"), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+    (stream, reader)
+}
+
+#[test]
+fn exchanges_websocket_frames() {
+    let (_server, client) = start(Router::new()
+        .websocket("/echo", |websocket| {
+            while let Ok(message) = websocket.recv() {
+                if let Message::Text(_) | Message::Binary(_) = message {
+                    let _ = websocket.send(message);
+                }
+            }
+        })
+        .websocket("/hang-up", |websocket| {
+            websocket.set_close_timeout(Duration::from_millis(200));
+            let _ = websocket.close(1000, "bye");
+        }));
+
+    // The handshake needs the upgrade headers and a well-formed key.
+    assert_eq!(client.get("/echo").unwrap().status, 426);
+    let response = client.request("GET", "/echo").header("Upgrade", "websocket").header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Version", "13").header("Sec-WebSocket-Key", "short").send().unwrap();
+    assert_eq!(response.status, 400);
+
+    let (mut stream, mut reader) = open_websocket(&client, "/echo");
+    write_masked_frame(&mut stream, 0x81, b"hello");
+    assert_eq!(read_frame(&mut reader), (0x81, b"hello".to_vec()));
+
+    // Fragments are joined, with a ping answered in between.
+    write_masked_frame(&mut stream, 0x01, b"frag");
+    write_masked_frame(&mut stream, 0x89, b"are you there");
+    write_masked_frame(&mut stream, 0x80, b"mented");
+    assert_eq!(read_frame(&mut reader), (0x8a, b"are you there".to_vec()));
+    assert_eq!(read_frame(&mut reader), (0x81, b"fragmented".to_vec()));
+
+    write_masked_frame(&mut stream, 0x88, &[0x03, 0xe8]);
+    assert_eq!(read_frame(&mut reader), (0x88, vec![0x03, 0xe8]));
+    assert_eq!(reader.read(&mut [0; 1]).unwrap(), 0);
+
+    // Unmasked client frames break the protocol and are answered with close code 1002.
+    let (mut stream, mut reader) = open_websocket(&client, "/echo");
+    stream.write_all(&[0x81, 0x02, b'h', b'i']).unwrap();
+    assert_eq!(read_frame(&mut reader), (0x88, vec![0x03, 0xea]));
+
+    // A peer that never answers the close does not hold the server for long.
+    let (_stream, mut reader) = open_websocket(&client, "/hang-up");
+    assert_eq!(read_frame(&mut reader), (0x88, b"\x03\xe8bye".to_vec()));
+    reader.get_ref().set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    assert_eq!(reader.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn streams_server_sent_events() {
+    let (_server, client) = start(Router::new().event_stream("/events", |events| {
+        // Resumes after the last event the client saw.
+        let first = events.last_event_id().and_then(|id| id.parse::<u32>().ok()).map_or(1, |id| id + 1);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for id in first..=3 {
+                thread::sleep(Duration::from_millis(150));
+                let _ = sender.send(Event::new(&format!("tick {id}\nline two")).id(&id.to_string()).event("tick"));
+            }
+        });
+        events.send(&Event::new("hello").retry(Duration::from_secs(2)))?;
+        events.forward(receiver, Duration::from_millis(50))
+    }));
+
+    let response = client.get("/events").unwrap();
+    assert_eq!(response.header("Content-Type"), Some("text/event-stream"));
+    assert_eq!(response.header("Cache-Control"), Some("no-cache"));
+    let text = response.text();
+    assert!(text.starts_with("retry: 2000\ndata: hello\n\n"));
+    assert!(text.contains(":\n\n"));
+    let events: Vec<&str> = text.split("\n\n").filter(|event| !event.is_empty() && *event != ":").collect();
+    assert_eq!(events, [
+        "retry: 2000\ndata: hello",
+        "id: 1\nevent: tick\ndata: tick 1\ndata: line two",
+        "id: 2\nevent: tick\ndata: tick 2\ndata: line two",
+        "id: 3\nevent: tick\ndata: tick 3\ndata: line two"
+    ]);
+
+    let response = client.request("GET", "/events").header("Last-Event-ID", "2").send().unwrap();
+    let events: Vec<String> = response.text().split("\n\n").filter(|event| event.starts_with("id: ")).map(String::from).collect();
+    assert_eq!(events, ["id: 3\nevent: tick\ndata: tick 3\ndata: line two"]);
+}
+
+// An upstream answering with its name, after a pause on /slow, and echoing the forwarding headers on /headers.
+fn start_upstream(name: &'static str) -> (ServerHandle, String) {
+    let (server, _) = start(Router::new()
+        .get("/name", move |_| HttpResponse::ok(name))
+        .get("/slow", move |_| {
+            thread::sleep(Duration::from_millis(400));
+            HttpResponse::ok(name)
+        })
+        .get("/headers", |request| {
+            let headers: Vec<String> = ["Host", "X-Forwarded-For", "X-Forwarded-Host", "X-Forwarded-Proto", "Forwarded"].iter()
+                .map(|name| format!("{name}: {}", request.header(name).unwrap_or("-")))
+                .collect();
+            HttpResponse::ok(headers.join("\n"))
+        }));
+    let address = server.local_addr().to_string();
+    (server, address)
+}
+
+fn get_closed_address() -> String {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string()
+}
+
+#[test]
+fn balances_requests_across_upstreams() {
+    let (_a, a) = start_upstream("a");
+    let (_b, b) = start_upstream("b");
+
+    let round_robin = Arc::new(UpstreamPool::new(&[&a, &b], Balancing::RoundRobin));
+    let (_server, client) = start(Router::new().proxy("/*", round_robin));
+    let names: Vec<String> = (0..4).map(|_| client.get("/name").unwrap().text()).collect();
+    assert_eq!(names, ["a", "b", "a", "b"]);
+
+    // While a slow request keeps one upstream busy, the others take the new requests.
+    let least_connections = Arc::new(UpstreamPool::new(&[&a, &b], Balancing::LeastConnections));
+    let (server, client) = start(Router::new().proxy("/*", Arc::clone(&least_connections)));
+    let slow_client = TestClient::new(server.local_addr());
+    let slow = thread::spawn(move || slow_client.get("/slow").unwrap().text());
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(least_connections.upstreams()[0].active_connections(), 1);
+    for _ in 0..2 {
+        assert_eq!(client.get("/name").unwrap().text(), "b");
+        // The upstream is counted until the server finishes relaying the body, just after the client has it.
+        while least_connections.upstreams()[1].active_connections() > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    assert_eq!(slow.join().unwrap(), "a");
+}
+
+#[test]
+fn fails_over_between_upstreams() {
+    let (_live, live) = start_upstream("live");
+    let dead = get_closed_address();
+
+    let pool = Arc::new(UpstreamPool::new(&[&dead, &live], Balancing::RoundRobin).max_failures(1, Duration::from_secs(60)));
+    let (_server, client) = start(Router::new().proxy("/*", Arc::clone(&pool)));
+    assert_eq!(client.get("/name").unwrap().text(), "live");
+    assert!(!pool.upstreams()[0].is_healthy());
+    assert!(pool.upstreams()[1].is_healthy());
+
+    let unreachable = Arc::new(UpstreamPool::new(&[&dead], Balancing::RoundRobin));
+    let slow = Arc::new(UpstreamPool::new(&[&live], Balancing::RoundRobin).response_timeout(Duration::from_millis(100)));
+    let (_server, client) = start(Router::new().proxy("/unreachable/*", unreachable).proxy("/*", slow));
+    assert_eq!(client.get("/unreachable/name").unwrap().status, 502);
+    assert_eq!(client.get("/slow").unwrap().status, 504);
+}
+
+#[test]
+fn passes_forwarding_headers_upstream() {
+    let (_upstream, address) = start_upstream("upstream");
+    let pool = Arc::new(UpstreamPool::new(&[&address], Balancing::RoundRobin));
+    let (_server, client) = start(Router::new().proxy("/*", pool));
+
+    let response = client.request("GET", "/headers").header("Host", "example.test").header("X-Forwarded-For", "10.0.0.1").send().unwrap();
+    assert_eq!(response.text(), format!("Host: {address}\nX-Forwarded-For: 10.0.0.1, 127.0.0.1\nX-Forwarded-Host: example.test\n\
+        X-Forwarded-Proto: http\nForwarded: for=127.0.0.1;host=\"example.test\";proto=http"));
+
+    // Quotes and backslashes in the host cannot break out of the quoted value.
+    let response = client.request("GET", "/headers").header("Host", "a\"b\\c").send().unwrap();
+    assert!(response.text().ends_with("Forwarded: for=127.0.0.1;host=\"a\\\"b\\\\c\";proto=http"), "{}", response.text());
+}
+
+#[test]
+fn routes_requests_by_host() {
+    let site = |name: &'static str| Site::new(Router::new().get("/", move |_| HttpResponse::ok(name)));
+    let virtual_hosts = VirtualHosts::new(site("default"))
+        .site(site("example").name("example.test").name("www.example.test"))
+        .site(site("wildcard").name("*.example.test"))
+        .site(site("api wildcard").name("*.api.example.test"));
+    let thread_pool = ThreadPool::new(2).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let handle = Server::new(thread_pool, move |request| virtual_hosts.handle(request)).start("127.0.0.1:0").unwrap();
+    let client = TestClient::new(handle.local_addr());
+
+    let site_for = |host: &str| client.request("GET", "/").header("Host", host).send().unwrap().text();
+    assert_eq!(site_for("example.test"), "example");
+    // Ports and case are ignored, and exact names beat wildcards.
+    assert_eq!(site_for("WWW.Example.test:8080"), "example");
+    assert_eq!(site_for("blog.example.test"), "wildcard");
+    assert_eq!(site_for("deep.blog.example.test"), "wildcard");
+    // The longest matching wildcard wins.
+    assert_eq!(site_for("v1.api.example.test"), "api wildcard");
+    // A wildcard needs a subdomain in front of it.
+    assert_eq!(site_for("api.example.test"), "wildcard");
+    assert_eq!(site_for("badexample.test"), "default");
+    assert_eq!(site_for("other.test"), "default");
+
+    // HTTP/1.1 requires a Host header; HTTP/1.0 goes to the default site without one.
+    assert_eq!(client.send_raw(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap().status, 400);
+    let response = client.send_raw(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "default");
+}
+
+#[test]
+fn signs_and_encodes_cookies() {
+    let signer = CookieSigner::new(b"secret key");
+    let reader = signer.clone();
+    let (_server, client) = start(Router::new()
+        .get("/set", move |_| HttpResponse::ok("set").with_cookie(&signer.signed_cookie("user", "alice")).with_cookie(&Cookie::new("note", "a;b, c\r\nSet-Cookie: x=1")))
+        .get("/get", move |request| HttpResponse::ok(format!("{:?} {:?}", reader.get_signed(request, "user"), request.cookie("note")))));
+
+    let response = client.get("/set").unwrap();
+    let set_cookies: Vec<&str> = response.headers.get_all("Set-Cookie").collect();
+    assert_eq!(set_cookies.len(), 2);
+    // Separators and line breaks are escaped, so they cannot end the cookie or the header.
+    assert_eq!(set_cookies[1], "note=a%3Bb%2C%20c%0D%0ASet-Cookie:%20x=1");
+    let cookies = set_cookies.join("; ");
+
+    let response = client.request("GET", "/get").header("Cookie", &cookies).send().unwrap();
+    assert_eq!(response.text(), "Some(\"alice\") Some(\"a;b, c\\r\\nSet-Cookie: x=1\")");
+
+    // Changing the value, or the signature, or moving it to another name invalidates it.
+    let signed = set_cookies[0].strip_prefix("user=").unwrap();
+    let (_, signature) = signed.rsplit_once('.').unwrap();
+    for cookie in [format!("user=mallory.{signature}"), format!("user={signed}x"), format!("other={signed}; user=alice")] {
+        let response = client.request("GET", "/get").header("Cookie", &cookie).send().unwrap();
+        assert!(response.text().starts_with("None "), "{cookie}");
+    }
+}
+
+#[test]
+fn keeps_sessions_in_files() {
+    let directory = std::env::temp_dir().join(format!("sessions-{}", std::process::id()));
+    let store = Arc::new(FileStore::new(&directory).unwrap());
+    let sessions = Arc::new(Sessions::new(Arc::clone(&store) as Arc<dyn SessionStore>).ttl(Duration::from_secs(60)));
+    let router = Router::new().get("/count", move |request| {
+        let mut session = sessions.load(request);
+        let count = session.get("count").and_then(|count| count.parse::<u32>().ok()).unwrap_or(0) + 1;
+        session.insert("count", &count.to_string());
+        sessions.save(session, HttpResponse::ok(count.to_string()))
+    });
+    let (_server, client) = start(router);
+
+    let first = client.get("/count").unwrap();
+    let cookie = first.header("Set-Cookie").unwrap().split(';').next().unwrap().to_string();
+    let id = cookie.strip_prefix("session=").unwrap();
+    assert!(directory.join(id).exists());
+    assert_eq!(client.request("GET", "/count").header("Cookie", &cookie).send().unwrap().text(), "2");
+    assert_eq!(store.load(id).unwrap().get("count").map(String::as_str), Some("2"));
+
+    // Ids that are not the store's own never reach the file system.
+    fs::write(directory.join("outside"), "4102444800\n{\"count\":\"9\"}").unwrap();
+    assert_eq!(store.load("../outside"), None);
+    assert_eq!(store.load("outside"), None);
+    assert!(store.save("../outside", &Default::default(), Duration::from_secs(60)).is_err());
+
+    // Expired sessions are not loaded, and are deleted along with unreadable files.
+    let expired = "0".repeat(64);
+    store.save(&expired, &Default::default(), Duration::ZERO).unwrap();
+    assert_eq!(store.load(&expired), None);
+    store.remove_expired().unwrap();
+    assert!(!directory.join(&expired).exists());
+    assert!(directory.join(id).exists());
+    assert_eq!(client.request("GET", "/count").header("Cookie", &cookie).send().unwrap().text(), "3");
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn waits_for_requests_in_the_reactor() {
+    let router = Router::new().post("/echo", |request| match request.read_body() {
+        Ok(body) => HttpResponse::ok(body),
+        Err(_) => HttpResponse::bad_request()
+    });
+    let thread_pool = ThreadPool::new(2).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let server = Server::new(thread_pool, move |request| router.handle(request)).with_keep_alive(Some(Duration::from_secs(60)));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TestClient::new(listener.local_addr().unwrap());
+    thread::spawn(move || Reactor::new().run(&server, listener));
+
+    // More clients than workers send half a request each; none of them holds up the others.
+    let mut streams: Vec<TcpStream> = (0..8).map(|index| {
+        let mut stream = client.connect().unwrap();
+        write!(stream, "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 7\r\n").unwrap();
+        if index % 2 == 0 {
+            write!(stream, "\r\nclie").unwrap();
+        }
+        stream
+    }).collect();
+    assert_eq!(client.request("POST", "/echo").body("direct").send().unwrap().text(), "direct");
+
+    for (index, stream) in streams.iter_mut().enumerate() {
+        match index % 2 {
+            0 => write!(stream, "nt{index}"),
+            _ => write!(stream, "Connection: close\r\n\r\nclient{index}")
+        }.unwrap();
+    }
+    let read_echo = |reader: &mut BufReader<TcpStream>| {
+        let (status, headers) = read_response_head(reader).unwrap();
+        assert_eq!(status, 200);
+        let length = headers.get("Content-Length").unwrap().parse().unwrap();
+        let mut body = String::new();
+        reader.take(length).read_to_string(&mut body).unwrap();
+        body
+    };
+    let mut readers: Vec<BufReader<TcpStream>> = streams.into_iter().map(BufReader::new).collect();
+    for (index, reader) in readers.iter_mut().enumerate() {
+        assert_eq!(read_echo(reader), format!("client{index}"));
+    }
+
+    // The kept-alive connections wait in the reactor, not on the workers, so others still get through.
+    let started = std::time::Instant::now();
+    assert_eq!(client.request("POST", "/echo").body("direct").send().unwrap().text(), "direct");
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    // Later requests on them are served, including two pipelined at once.
+    for (index, reader) in readers.iter_mut().enumerate().filter(|(index, _)| index % 2 == 0) {
+        let request = format!("POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\r\nagain{index}");
+        let requests = request.repeat(if index == 0 { 2 } else { 1 });
+        reader.get_mut().write_all(requests.as_bytes()).unwrap();
+        assert_eq!(read_echo(reader), format!("again{index}"));
+    }
+    assert_eq!(read_echo(&mut readers[0]), "again0");
+}
+
+#[test]
+fn negotiates_error_pages() {
+    let directory = std::env::temp_dir().join(format!("error-pages-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("404.html"), "<h1>Lost</h1>").unwrap();
+    fs::write(directory.join("error.html"), "<h1>{{ status }} {{ reason }} at {{ path }}</h1>").unwrap();
+    let templates = Arc::new(Templates::new(&directory));
+
+    let error_pages = ErrorPages::new()
+        .page(404, ErrorPage::File(directory.join("404.html")))
+        .page(500, ErrorPage::Template(Arc::clone(&templates), "missing.html".into()))
+        .default_page(ErrorPage::Template(templates, "error.html".into()));
+    let router = Router::new()
+        .get("/fail", |_| HttpResponse::internal_server_error())
+        .get("/json", |_| HttpResponse::json_error(409, "taken"))
+        .get("/gone", |_| HttpResponse::new(410, "text/plain", "moved to /new"))
+        .get("/empty", |_| HttpResponse::new(403, "text/plain", ""));
+    let thread_pool = ThreadPool::new(2).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let server = Server::new(thread_pool, move |request| router.handle(request))
+        .with_middleware(error_pages)
+        .start("127.0.0.1:0")
+        .unwrap();
+    let client = TestClient::new(server.local_addr());
+
+    assert_eq!(client.get("/nowhere").unwrap().text(), "<h1>Lost</h1>");
+
+    // A page that fails to render falls back to the default page.
+    let response = client.get("/fail").unwrap();
+    assert_eq!(response.status, 500);
+    assert_eq!(response.text(), "<h1>500 Internal Server Error at /fail</h1>");
+
+    let response = client.request("DELETE", "/fail").send().unwrap();
+    assert_eq!(response.header("Allow"), Some("GET, HEAD"));
+    assert_eq!(response.text(), "<h1>405 Method Not Allowed at /fail</h1>");
+
+    let response = client.request("GET", "/nowhere").header("Accept", "application/json, text/html;q=0.9").send().unwrap();
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    assert_eq!(response.json::<serde_json::Value>().unwrap()["error"]["message"], "Not Found");
+
+    let response = client.request("GET", "/json").header("Accept", "text/html").send().unwrap();
+    assert_eq!(response.json::<serde_json::Value>().unwrap()["error"]["message"], "taken");
+
+    // Bodies a handler wrote itself are kept; empty ones get the page, with a matching length for HEAD.
+    let response = client.get("/gone").unwrap();
+    assert_eq!((response.header("Content-Type"), response.text().as_str()), (Some("text/plain"), "moved to /new"));
+    let response = client.get("/empty").unwrap();
+    assert_eq!((response.header("Content-Type"), response.text().as_str()), (Some("text/html"), "<h1>403 Forbidden at /empty</h1>"));
+    let response = client.request("HEAD", "/nowhere").send().unwrap();
+    assert_eq!(response.header("Content-Length"), Some("<h1>Lost</h1>".len().to_string().as_str()));
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn caches_static_files() {
+    let directory = std::env::temp_dir().join(format!("file-cache-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(directory.join(name), name).unwrap();
+    }
+
+    let file_cache = Arc::new(FileCache::new(1024, 2));
+    let stats = file_cache.stats();
+    let site = Site::new(Router::new()).document_root(&directory).file_cache(Arc::clone(&file_cache));
+    let virtual_hosts = VirtualHosts::new(site);
+    let thread_pool = ThreadPool::new(2).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let server = Server::new(thread_pool, move |request| virtual_hosts.handle(request)).start("127.0.0.1:0").unwrap();
+    let client = TestClient::new(server.local_addr());
+
+    let response = client.get("/a.txt").unwrap();
+    assert_eq!(response.header("Content-Type"), Some("text/plain; charset=utf-8"));
+    assert_eq!(response.text(), "a.txt");
+    assert_eq!(client.get("/a.txt").unwrap().text(), "a.txt");
+    assert_eq!((stats.hits(), stats.misses()), (1, 1));
+
+    // Rewriting the file with a different modification time invalidates it.
+    fs::write(directory.join("a.txt"), "changed").unwrap();
+    File::options().write(true).open(directory.join("a.txt")).unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+    assert_eq!(client.get("/a.txt").unwrap().text(), "changed");
+    assert_eq!((stats.hits(), stats.misses()), (1, 2));
+
+    // With room for two files, reading a third evicts the least recently used.
+    client.get("/b.txt").unwrap();
+    client.get("/a.txt").unwrap();
+    client.get("/c.txt").unwrap();
+    assert_eq!(stats.evictions(), 1);
+    assert_eq!((file_cache.len(), file_cache.size()), (2, "changed".len() + "c.txt".len()));
+    client.get("/a.txt").unwrap();
+    assert_eq!((stats.hits(), stats.misses()), (3, 4));
+
+    assert_eq!(client.get("/missing.txt").unwrap().status, 404);
+
+    // Files over the per-entry limit are sent from disk, so they are neither cached nor counted.
+    let large = "0123456789".repeat(10);
+    fs::write(directory.join("large.txt"), &large).unwrap();
+    let small_entries = FileCache::new(1024, 2).max_entry_size(64);
+    let request = read_request(&mut &b"GET /large.txt HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]).unwrap();
+    assert!(matches!(small_entries.serve(&directory, &request).unwrap().body, Body::File(_, 100)));
+    let request = read_request(&mut &b"GET /c.txt HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]).unwrap();
+    assert!(matches!(small_entries.serve(&directory, &request).unwrap().body, Body::Shared(_)));
+    assert_eq!((small_entries.len(), small_entries.stats().misses()), (1, 1));
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn sends_static_files_from_disk() {
+    let directory = std::env::temp_dir().join(format!("sendfile-{}", std::process::id()));
+    fs::create_dir_all(directory.join("sub")).unwrap();
+    let contents: Vec<u8> = (0..3_000_000u32).map(|index| (index % 251) as u8).collect();
+    fs::write(directory.join("large.bin"), &contents).unwrap();
+
+    let virtual_hosts = VirtualHosts::new(Site::new(Router::new()).document_root(&directory));
+    let thread_pool = ThreadPool::new(2).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let server = Server::new(thread_pool, move |request| virtual_hosts.handle(request)).start("127.0.0.1:0").unwrap();
+    let client = TestClient::new(server.local_addr());
+
+    let response = client.get("/large.bin").unwrap();
+    assert_eq!(response.header("Content-Length"), Some("3000000"));
+    assert_eq!(response.body, contents);
+
+    // A directory without an index.html is not a file.
+    assert_eq!(client.get("/sub").unwrap().status, 404);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn serves_dual_stack_and_unix_socket_listeners() {
+    let socket_path = std::env::temp_dir().join(format!("listener-{}.sock", std::process::id()));
+    let tcp_listener = Listener::bind_tcp("[::]:0").unwrap();
+    let port = tcp_listener.local_addr().unwrap().port();
+    let unix_listener = Listener::bind_unix(&socket_path, 0o600).unwrap();
+    assert_eq!(fs::metadata(&socket_path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let router = Router::new().get("/", |request| HttpResponse::ok(format!("{:?}", request.remote_addr.map(|addr| addr.is_ipv6()))));
+    let thread_pool = ThreadPool::new(2).unwrap_or_else(|_| panic!("Failed to create thread pool"));
+    let server = Arc::new(Server::new(thread_pool, move |request| router.handle(request)));
+    for listener in [tcp_listener, unix_listener] {
+        let server = Arc::clone(&server);
+        thread::spawn(move || server.serve(&listener));
+    }
+
+    // IPv4 clients reach the IPv6 socket through mapped addresses.
+    assert_eq!(TestClient::new(SocketAddr::from(([127, 0, 0, 1], port))).get("/").unwrap().text(), "Some(true)");
+
+    let mut stream = UnixStream::connect(&socket_path).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nNone"));
+
+    fs::remove_file(socket_path).unwrap();
+}
+
+#[test]
+fn drains_and_exits_on_sigterm() {
+    for mode in [None, Some("--reactor")] {
+        let address = get_closed_address();
+        let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_rust_web_server"))
+            .args(["--listen", address.as_str(), "--drain", "1"])
+            .args(mode)
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let client = TestClient::new(address.parse().unwrap());
+
+        let mut ready = client.get("/readyz");
+        while ready.is_err() {
+            thread::sleep(Duration::from_millis(20));
+            ready = client.get("/readyz");
+        }
+        assert_eq!(ready.unwrap().status, 200);
+
+        // An idle kept-alive connection, and clients connecting until the server stops accepting.
+        let mut idle = BufReader::new(client.connect().unwrap());
+        idle.get_mut().write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert_eq!(read_response_head(&mut idle).unwrap().0, 200);
+        let load = thread::spawn(move || {
+            let mut served = 0;
+            while let Ok(response) = client.get("/healthz") {
+                assert_eq!(response.status, 200);
+                served += 1;
+            }
+            served
+        });
+
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+        thread::sleep(Duration::from_millis(300));
+        // Still answering while it drains, but no longer ready.
+        let client = TestClient::new(address.parse().unwrap());
+        assert_eq!(client.get("/readyz").unwrap().status, 503);
+        assert_eq!(client.get("/healthz").unwrap().status, 200);
+
+        let started = std::time::Instant::now();
+        while child.try_wait().unwrap().is_none() {
+            assert!(started.elapsed() < Duration::from_secs(10), "server did not exit");
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(child.wait().unwrap().success());
+        assert!(load.join().unwrap() > 0);
+    }
+}
+
+#[test]
+fn serves_sockets_passed_by_the_service_manager() {
+    use std::os::{fd::AsRawFd, unix::process::CommandExt};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fd = listener.as_raw_fd();
+
+    // The shell keeps its process id through exec, so LISTEN_PID names the server as systemd would.
+    let mut command = std::process::Command::new("sh");
+    command.args(["-c", "LISTEN_PID=$$ LISTEN_FDS=1 exec \"$0\"", env!("CARGO_BIN_EXE_rust_web_server")])
+        .stdout(std::process::Stdio::null());
+    // Only async-signal-safe calls between fork and exec: the socket becomes descriptor 3, kept open across exec.
+    unsafe {
+        command.pre_exec(move || {
+            let result = match fd {
+                3 => libc::fcntl(3, libc::F_SETFD, 0),
+                _ => libc::dup2(fd, 3)
+            };
+            match result {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(())
+            }
+        });
+    }
+    let mut child = command.spawn().unwrap();
+    drop(listener);
+
+    let client = TestClient::new(addr);
+    let response = client.get("/readyz");
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGKILL) };
+    child.wait().unwrap();
+    assert_eq!(response.unwrap().status, 200);
+}
+
+#[test]
+fn reads_chunked_uploads_with_trailers() {
+    let (_server, client) = start(Router::new().post("/upload", |request| {
+        let Ok(body) = request.read_body() else {
+            return HttpResponse::bad_request();
+        };
+        let checksum = request.trailers().get("Checksum").unwrap_or("none").to_string();
+        HttpResponse::ok(format!("{} {checksum}", String::from_utf8_lossy(&body)))
+    }));
+
+    let response = client.send_raw(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+        5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nChecksum: abc\r\n\r\n").unwrap();
+    assert_eq!(response.text(), "hello, world abc");
+
+    // Chunks larger than their declared size, or a length given twice, are rejected.
+    let response = client.send_raw(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nabc\r\n0\r\n\r\n").unwrap();
+    assert_eq!(response.status, 400);
+    assert_eq!(response.header("Connection"), Some("close"));
+    let response = client.send_raw(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n0\r\n\r\n").unwrap();
+    assert_eq!(response.status, 400);
+    let response = client.send_raw(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip\r\n\r\n").unwrap();
+    assert_eq!(response.status, 501);
+
+    // Lengths that disagree, in separate headers or in one list, are rejected; repeating the same one is not.
+    for lengths in ["Content-Length: 5\r\nContent-Length: 7", "Content-Length: 5, 7", "Content-Length: +5"] {
+        let request = format!("POST /upload HTTP/1.1\r\nHost: localhost\r\n{lengths}\r\n\r\nhello, world");
+        let response = client.send_raw(request.as_bytes()).unwrap();
+        assert_eq!((response.status, response.header("Connection")), (400, Some("close")), "{lengths}");
+    }
+    let response = client.send_raw(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5, 5\r\nConnection: close\r\n\r\nhello").unwrap();
+    assert_eq!(response.text(), "hello none");
+}
+
+#[test]
+fn streams_chunked_responses_on_kept_alive_connections() {
+    let (_server, client) = start(Router::new()
+        .get("/", |_| HttpResponse::ok("home"))
+        .get("/stream", |_| HttpResponse::stream(200, "text/plain", Box::new(|writer| {
+            writer.write_all(b"first ")?;
+            writer.flush()?;
+            writer.write_all(b"second")
+        }))));
+
+    let stream = client.connect().unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+
+    writer.write_all(b"GET /stream HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let (status, headers) = read_response_head(&mut reader).unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(headers.get("Transfer-Encoding"), Some("chunked"));
+    assert_eq!(headers.get("Connection"), None);
+    let mut body = String::new();
+    ChunkedReader::new(&mut reader).read_to_string(&mut body).unwrap();
+    assert_eq!(body, "first second");
+
+    writer.write_all(b"HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let (status, headers) = read_response_head(&mut reader).unwrap();
+    assert_eq!((status, headers.get("Content-Length")), (200, Some("4")));
+
+    writer.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let (_, headers) = read_response_head(&mut reader).unwrap();
+    assert_eq!(headers.get("Connection"), Some("close"));
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "home");
+
+    // HTTP/1.0 clients get the stream delimited by the connection closing instead.
+    let response = client.send_raw(b"GET /stream HTTP/1.0\r\n\r\n").unwrap();
+    assert_eq!(response.header("Transfer-Encoding"), None);
+    assert_eq!(response.text(), "first second");
+    assert!(reader.fill_buf().unwrap().is_empty());
+}
+
+#[test]
+fn reframes_chunked_upstream_responses() {
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = upstream.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for stream in upstream.incoming().flatten() {
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            reader.get_mut().write_all(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </style.css>\r\n\r\n\
+                HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n").unwrap();
+        }
+    });
+    let pool = Arc::new(UpstreamPool::new(&[&address], Balancing::RoundRobin));
+    let (_server, client) = start(Router::new().proxy("/*", pool));
+
+    // Decoded, then chunked again for an HTTP/1.1 client, with only the final response relayed.
+    let response = client.get("/").unwrap();
+    assert_eq!((response.status, response.header("Link")), (200, None));
+    assert_eq!(response.header("Transfer-Encoding"), Some("chunked"));
+    assert_eq!(response.text(), "hello world");
+
+    // An HTTP/1.0 client cannot read chunks, so the body ends when the connection closes.
+    let response = client.send_raw(b"GET / HTTP/1.0\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n").unwrap();
+    assert_eq!((response.header("Transfer-Encoding"), response.header("Connection")), (None, Some("close")));
+    assert_eq!(response.text(), "hello world");
+}
+
+#[test]
+fn answers_expect_continue_only_when_the_body_is_wanted() {
+    let hash = bcrypt::hash("secret", 4).unwrap();
+    let htpasswd = Arc::new(Htpasswd::parse(&format!("ann:{hash}")).unwrap());
+    let (_server, client) = start(Router::new()
+        .post("/upload", |request| match read_limited(request, 10) {
+            Ok(body) => HttpResponse::ok(body),
+            Err(error) => error.get_response()
+        })
+        .middleware(BasicAuth::new("Uploads", htpasswd)));
+
+    let read_head = |reader: &mut BufReader<TcpStream>| {
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            assert!(reader.read_line(&mut head).unwrap() > 0);
+        }
+        head
+    };
+    let upload = |headers: &str| {
+        let stream = client.connect().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        writer.write_all(format!("POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\n{headers}\r\n").as_bytes()).unwrap();
+        let head = read_head(&mut reader);
+        (head, reader, writer)
+    };
+
+    // "ann:secret"
+    let (head, mut reader, mut writer) = upload("Authorization: Basic YW5uOnNlY3JldA==\r\nContent-Length: 5\r\n");
+    assert_eq!(head, "HTTP/1.1 100 Continue\r\n\r\n");
+    writer.write_all(b"hello").unwrap();
+    assert!(read_head(&mut reader).starts_with("HTTP/1.1 200 OK\r\n"));
+
+    // Rejected before the body is asked for, and the connection is not reused for it.
+    let (head, _, _) = upload("Content-Length: 5\r\n");
+    assert!(head.starts_with("HTTP/1.1 401 Unauthorized\r\n") && head.contains("Connection: close\r\n"));
+    let (head, _, _) = upload("Authorization: Basic YW5uOnNlY3JldA==\r\nContent-Length: 50\r\n");
+    assert!(head.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+
+    let response = client.send_raw(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: something-else\r\n\r\n").unwrap();
+    assert_eq!(response.status, 417);
+}
+
+#[test]
+fn runs_cgi_programs() {
+    let script = std::env::temp_dir().join(format!("cgi-{}.sh", std::process::id()));
+    fs::write(&script, "#!/bin/sh\nprintf 'Status: 201 Created\\r\\nContent-Type: text/plain\\r\\n\\r\\n'\n\
+        echo \"$REQUEST_METHOD $SCRIPT_NAME $PATH_INFO $QUERY_STRING $CONTENT_LENGTH $HTTP_X_TOKEN\"\ncat\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let slow_script = std::env::temp_dir().join(format!("cgi-slow-{}.sh", std::process::id()));
+    fs::write(&slow_script, "#!/bin/sh\nexec sleep 5\n").unwrap();
+    fs::set_permissions(&slow_script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let (_server, client) = start(Router::new()
+        .cgi("/cgi-bin/app/*", Arc::new(Cgi::new(&script)))
+        .cgi("/slow", Arc::new(Cgi::new(&slow_script).timeout(Duration::from_millis(200))))
+        .cgi("/missing", Arc::new(Cgi::new("/nonexistent/program"))));
+
+    let response = client.request("POST", "/cgi-bin/app/users/1?full=yes").header("X-Token", "abc").body("payload").send().unwrap();
+    assert_eq!(response.status, 201);
+    assert_eq!(response.header("Content-Type"), Some("text/plain"));
+    assert_eq!(response.text(), "POST /cgi-bin/app /users/1 full=yes 7 abc\npayload");
+
+    // The output is drained while the body is still being written, so echoing a large one does not deadlock.
+    let body = "0123456789abcdef".repeat(32 * 1024);
+    let response = client.request("POST", "/cgi-bin/app/echo").body(body.as_str()).send().unwrap();
+    assert_eq!(response.status, 201);
+    assert_eq!(response.text(), format!("POST /cgi-bin/app /echo  {} \n{body}", body.len()));
+
+    assert_eq!(client.get("/slow").unwrap().status, 504);
+    assert_eq!(client.get("/missing").unwrap().status, 502);
+    fs::remove_file(script).unwrap();
+    fs::remove_file(slow_script).unwrap();
+}
+
+// Answers one FastCGI request with its SCRIPT_FILENAME parameter and standard input.
+fn respond_to_fastcgi(mut stream: TcpStream) {
+    let mut params = Vec::new();
+    let mut stdin = Vec::new();
+    loop {
+        let mut header = [0; 8];
+        stream.read_exact(&mut header).unwrap();
+        let mut content = vec![0; u16::from_be_bytes([header[4], header[5]]) as usize + header[6] as usize];
+        stream.read_exact(&mut content).unwrap();
+        content.truncate(u16::from_be_bytes([header[4], header[5]]) as usize);
+        match header[1] {
+            4 => params.extend(content),
+            5 if content.is_empty() => break,
+            5 => stdin.extend(content),
+            _ => {}
+        }
+    }
+
+    let mut script_filename = String::new();
+    let mut index = 0;
+    while index < params.len() {
+        let (name_length, value_length) = (params[index] as usize, params[index + 1] as usize);
+        let name = &params[index + 2..index + 2 + name_length];
+        let value = &params[index + 2 + name_length..index + 2 + name_length + value_length];
+        if name == b"SCRIPT_FILENAME" {
+            script_filename = String::from_utf8_lossy(value).into_owned();
+        }
+        index += 2 + name_length + value_length;
+    }
+
+    let output = format!("Content-Type: text/plain\r\n\r\n{script_filename} {}", String::from_utf8_lossy(&stdin));
+    let mut record = vec![1, 6, 0, 1, 0, output.len() as u8, 0, 0];
+    record.extend_from_slice(output.as_bytes());
+    record.extend_from_slice(&[1, 6, 0, 1, 0, 0, 0, 0, 1, 3, 0, 1, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    stream.write_all(&record).unwrap();
+}
+
+#[test]
+fn forwards_requests_to_fastcgi_responders() {
+    let responder = TcpListener::bind("127.0.0.1:0").unwrap();
+    let responder_addr = responder.local_addr().unwrap().to_string();
+    thread::spawn(move || respond_to_fastcgi(responder.accept().unwrap().0));
+
+    let closed_addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let (_server, client) = start(Router::new()
+        .fastcgi("/app.php", Arc::new(FastCgi::new(&responder_addr).param("SCRIPT_FILENAME", "/srv/app.php")))
+        .fastcgi("/down.php", Arc::new(FastCgi::new(&closed_addr))));
+
+    let response = client.request("POST", "/app.php").body("name=ann").send().unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "/srv/app.php name=ann");
+
+    assert_eq!(client.get("/down.php").unwrap().status, 502);
+}
+
+#[test]
+fn benchmarks_a_server() {
+    let (server, _) = start(Router::new().get("/", |_| HttpResponse::ok("home")));
+    let url = format!("http://{}/", server.local_addr());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_bench"))
+        .args([url.as_str(), "--connections", "1", "--requests", "40", "--request", "GET", "/", "3", "--request", "GET", "/missing", "1"])
+        .output()
+        .unwrap();
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(report.contains("Requests:     40 in "), "{report}");
+    assert!(report.contains("0 errors"), "{report}");
+    assert!(report.contains("Status codes: 200 x 30, 404 x 10"), "{report}");
+    assert!(report.contains("p99 "), "{report}");
+}